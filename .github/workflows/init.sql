@@ -1,9 +1,32 @@
 CREATE TABLE users (
     id SERIAL PRIMARY KEY,
     username VARCHAR(50) UNIQUE NOT NULL,
-    pwd VARCHAR(50) NOT NULL
+    -- An Argon2id PHC string, around 100 characters with the default parameters.
+    pwd VARCHAR(255) NOT NULL
 );
 
+-- Plaintext on purpose: legacy rows like these are replaced by a hash on the first
+-- successful login.
 INSERT INTO users (username, pwd) VALUES
 ('mock1', 'password1'),
 ('mock2', 'password2');
+
+-- Server-side sessions, the id is what the session cookie carries.
+CREATE TABLE sessions (
+    id CHAR(64) PRIMARY KEY,
+    username VARCHAR(50),
+    data JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL,
+    last_seen_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX sessions_last_seen_at ON sessions (last_seen_at);
+
+-- Failed logins per username, known or not, and the lockout they led to.
+-- 'ironcladserver unlock USERNAME' deletes a row.
+CREATE TABLE login_lockouts (
+    username TEXT PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    last_failure_at TIMESTAMPTZ NOT NULL,
+    locked_until TIMESTAMPTZ
+);
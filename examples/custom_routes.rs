@@ -0,0 +1,109 @@
+/// This example serves your own routes instead of the built-in ones, the way you would
+//  when using ironcladserver as a library. To run it use:
+//    "cargo run --example custom_routes start -ip 127.0.0.1 -p 7878 --notls"
+//  and then try 'curl http://127.0.0.1:7878/hello/crab'
+//  or 'curl -F file=@Cargo.toml http://127.0.0.1:7878/upload'.
+extern crate ironcladserver;
+use ironcladserver::cli::{Config, ServerCommand};
+use ironcladserver::error::FormError;
+use ironcladserver::middleware::{HandlerExt, Logger, SecurityHeaders};
+use ironcladserver::multipart::{Multipart, MultipartConfig};
+use ironcladserver::request::Request;
+use ironcladserver::response::Response;
+use ironcladserver::router::Router;
+use ironcladserver::status::StatusCode;
+use ironcladserver::Server;
+use std::env;
+use std::error::Error;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Registered with 'Server::with_state', so every connection counts into the same one.
+struct Greetings(AtomicU64);
+
+async fn hello(request: Request) -> Response {
+    let name = request.params.get("name").unwrap_or("stranger").to_string();
+    let count = match request.state::<Greetings>() {
+        Some(greetings) => greetings.0.fetch_add(1, Ordering::Relaxed) + 1,
+        None => 0,
+    };
+    Response::builder(StatusCode::Ok)
+        .content_type("text/plain; charset=UTF-8")
+        .body(format!("Hello, {}! (greeting #{})", name, count))
+}
+
+// Keeps uploaded files in './uploads', where the server streamed them while reading the
+// request. The client's file name is only used for the reply, files are stored under
+// the random name the upload got.
+async fn upload(request: Request) -> Response {
+    let config = request
+        .state::<MultipartConfig>()
+        .cloned()
+        .unwrap_or_default();
+    let form = match Multipart::from_request(&request, &config).await {
+        Ok(form) => form,
+        Err(FormError::Http(e)) => {
+            return Response::builder(StatusCode::BadRequest).body(e.to_string());
+        }
+        Err(FormError::Io(e)) => {
+            eprintln!("{}", e);
+            return Response::new(StatusCode::InternalServerError);
+        }
+    };
+
+    let mut stored = Vec::new();
+    for file in form.into_files() {
+        let name = file.file_name.clone().unwrap_or_default();
+        let size = file.size;
+        let path = file.path().with_extension("keep");
+        if let Err(e) = file.persist(&path).await {
+            eprintln!("{}", e);
+            return Response::new(StatusCode::InternalServerError);
+        }
+        stored.push(format!("{} ({} bytes)", name, size));
+    }
+    Response::builder(StatusCode::Created)
+        .content_type("text/plain; charset=UTF-8")
+        .body(format!("Stored: {}", stored.join(", ")))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli_input: Vec<String> = env::args().collect();
+    let config = Config::build(&cli_input).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(0);
+    });
+    if config.command != ServerCommand::Start {
+        eprintln!("This example only supports the 'start' command.");
+        process::exit(0);
+    }
+
+    let router = Router::new()
+        .get("/hello/:name", hello)
+        .post("/upload", upload)
+        .get("/health", |_| async {
+            Response::new(StatusCode::NoContent)
+        })
+        .layer(SecurityHeaders::default())
+        .layer(Logger);
+    // The server writes multipart uploads with these limits, on any route.
+    let uploads = MultipartConfig {
+        upload_dir: Path::new("uploads").to_path_buf(),
+        max_files: 4,
+        max_file_size: 8 * 1024 * 1024,
+        max_body_size: 32 * 1024 * 1024,
+        ..MultipartConfig::default()
+    };
+    tokio::fs::create_dir_all(&uploads.upload_dir).await?;
+    let server = Server::init(config.args_opts_map.unwrap())?
+        .with_state(Greetings(AtomicU64::new(0)))
+        .with_state(uploads)
+        .with_handler(router);
+    match server.with_tls {
+        true => server.start_async_tls().await?,
+        false => server.start_async().await?,
+    }
+    Ok(())
+}
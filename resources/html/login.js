@@ -0,0 +1,4 @@
+// login.html is static, so the form's CSRF token is copied from the CSRF cookie.
+const csrf = document.cookie.split('; ')
+    .find(cookie => cookie.startsWith('__Host-csrf=') || cookie.startsWith('csrf='));
+document.getElementById('csrf_token').value = csrf ? csrf.substring(csrf.indexOf('=') + 1) : '';
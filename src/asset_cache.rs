@@ -0,0 +1,163 @@
+#![forbid(unsafe_code)]
+
+use crate::conditional::EntityTag;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::io::Result as IoResult;
+
+/// A small file held in memory, along with the validators sent for it.
+#[derive(Debug, Clone)]
+pub struct Asset {
+    pub content: Bytes,
+    pub etag: EntityTag,
+    pub last_modified: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    asset: Asset,
+    len: u64,
+}
+
+/// An in-memory cache of small files such as the error pages and the favicon.
+///
+/// Every lookup stats the file and reloads it when its size or modification time
+/// changed, so edits on disk show up on the next request without a restart. Clones
+/// share the same cache.
+#[derive(Debug, Clone)]
+pub struct AssetCache {
+    entries: Arc<RwLock<HashMap<PathBuf, Entry>>>,
+    max_asset_size: u64,
+    max_total_size: u64,
+}
+
+impl Default for AssetCache {
+    fn default() -> AssetCache {
+        AssetCache::new()
+    }
+}
+
+impl AssetCache {
+    pub fn new() -> AssetCache {
+        AssetCache {
+            entries: Arc::new(RwLock::new(HashMap::new())),
+            max_asset_size: 256 * 1024,
+            max_total_size: 16 * 1024 * 1024,
+        }
+    }
+
+    /// Files larger than this are never cached.
+    pub fn max_asset_size(mut self, max_asset_size: u64) -> AssetCache {
+        self.max_asset_size = max_asset_size;
+        self
+    }
+
+    /// Once the cached files add up to this, new ones are read but not kept.
+    pub fn max_total_size(mut self, max_total_size: u64) -> AssetCache {
+        self.max_total_size = max_total_size;
+        self
+    }
+
+    /// The file at 'path', from memory if it hasn't changed since it was cached.
+    /// Ok(None) if it's not a regular file or too large to cache, the caller should
+    /// read it from disk then.
+    pub async fn get(&self, path: &Path) -> IoResult<Option<Asset>> {
+        let metadata = fs::metadata(path).await?;
+        if !metadata.is_file() || metadata.len() > self.max_asset_size {
+            self.remove(path);
+            return Ok(None);
+        }
+        let modified = metadata.modified().ok();
+        if let Some(entry) = self.read().get(path) {
+            if entry.len == metadata.len() && entry.asset.last_modified == modified {
+                return Ok(Some(entry.asset.clone()));
+            }
+        }
+
+        let content = Bytes::from(fs::read(path).await?);
+        let asset = Asset {
+            etag: EntityTag::from_metadata(path, &metadata),
+            content,
+            last_modified: modified,
+        };
+        // The file may have changed again while we read it, then the next lookup reloads it.
+        let len = asset.content.len() as u64;
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        let cached: u64 = entries
+            .iter()
+            .filter(|(cached_path, _)| cached_path.as_path() != path)
+            .map(|(_, entry)| entry.len)
+            .sum();
+        if cached + len <= self.max_total_size {
+            let entry = Entry {
+                asset: asset.clone(),
+                len,
+            };
+            entries.insert(path.to_path_buf(), entry);
+        } else {
+            entries.remove(path);
+        }
+        Ok(Some(asset))
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<PathBuf, Entry>> {
+        self.entries.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn remove(&self, path: &Path) {
+        if self.read().contains_key(path) {
+            self.entries
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn reloads_files_that_changed() {
+        let dir = TempDir::new("assets");
+        let path = dir.join("page.html");
+        std::fs::write(&path, "<p>one</p>").unwrap();
+        let cache = AssetCache::new();
+
+        let first = cache.get(&path).await.unwrap().unwrap();
+        assert_eq!(first.content, "<p>one</p>");
+        let again = cache.get(&path).await.unwrap().unwrap();
+        assert_eq!(again.etag, first.etag);
+
+        // Same size, so only the modification time tells them apart.
+        std::fs::write(&path, "<p>two</p>").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        let changed = cache.get(&path).await.unwrap().unwrap();
+        assert_eq!(changed.content, "<p>two</p>");
+        assert_ne!(changed.etag, first.etag);
+    }
+
+    #[tokio::test]
+    async fn skips_large_files() {
+        let dir = TempDir::new("assets");
+        let path = dir.join("large.bin");
+        std::fs::write(&path, vec![0u8; 100]).unwrap();
+        let cache = AssetCache::new().max_asset_size(64);
+        assert!(cache.get(&path).await.unwrap().is_none());
+
+        let cache = AssetCache::new().max_total_size(64);
+        let asset = cache.get(&path).await.unwrap().unwrap();
+        assert_eq!(asset.content.len(), 100);
+        assert!(cache.read().is_empty());
+        assert!(cache.get(&dir.join("missing")).await.is_err());
+    }
+}
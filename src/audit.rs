@@ -0,0 +1,72 @@
+#![forbid(unsafe_code)]
+
+use chrono::{SecondsFormat, Utc};
+use std::fmt;
+use std::time::Duration;
+
+/// How a login attempt ended. Only the audit trail sees the reason for a failure,
+/// clients get the same 401 either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginOutcome {
+    Success,
+    UnknownUser,
+    WrongPassword,
+    /// Refused without checking the password, the username had too many failures.
+    LockedOut,
+}
+
+impl fmt::Display for LoginOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoginOutcome::Success => write!(f, "success"),
+            LoginOutcome::UnknownUser => write!(f, "failure reason=unknown_user"),
+            LoginOutcome::WrongPassword => write!(f, "failure reason=wrong_password"),
+            LoginOutcome::LockedOut => write!(f, "failure reason=locked_out"),
+        }
+    }
+}
+
+/// Records a login attempt in the server-side audit trail.
+pub fn login(username: &str, outcome: LoginOutcome) {
+    println!("{}", login_line(username, outcome));
+}
+
+/// Records that a username was locked after too many failed logins.
+pub fn lockout(username: &str, duration: Duration) {
+    println!(
+        "[ AUDIT ]    {} lockout user={:?} seconds={}",
+        now(),
+        username,
+        duration.as_secs()
+    );
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// The username is quoted and escaped, it comes from the client and must not be able
+// to forge extra lines or fields.
+fn login_line(username: &str, outcome: LoginOutcome) -> String {
+    format!(
+        "[ AUDIT ]    {} login user={:?} {}",
+        now(),
+        username,
+        outcome
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_usernames() {
+        let line = login_line("eve\n[ AUDIT ] admin", LoginOutcome::WrongPassword);
+        assert!(line.starts_with("[ AUDIT ]    "));
+        assert!(
+            line.ends_with(r#" login user="eve\n[ AUDIT ] admin" failure reason=wrong_password"#)
+        );
+        assert_eq!(line.lines().count(), 1);
+    }
+}
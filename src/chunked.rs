@@ -0,0 +1,263 @@
+#![forbid(unsafe_code)]
+
+use crate::error::HttpError;
+use crate::headers::Headers;
+use crate::request::parse_header_line;
+
+// A chunk size is at most 16 hex digits, so anything longer than this is
+// extensions padding and not worth buffering.
+const MAX_CHUNK_SIZE_LINE: usize = 1024;
+// Size lines, extensions and CRLFs together may take as much as the body itself, but
+// no less than this so small bodies can still be sent in many small chunks.
+const MIN_FRAMING_SIZE: usize = 64 * 1024;
+
+/// Incremental decoder for `Transfer-Encoding: chunked` bodies (RFC 9112, 7.1).
+///
+/// Like `RequestParser`, it is fed everything received so far (starting right after the
+/// request head) and picks up where it stopped on the previous call.
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    state: DecoderState,
+    pos: usize,
+    body: Vec<u8>,
+    trailers: Headers,
+    trailers_size: usize,
+    framing_size: usize,
+    max_body_size: usize,
+    max_trailers_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DecoderState {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+impl ChunkedDecoder {
+    pub fn new(max_body_size: usize, max_trailers_size: usize) -> ChunkedDecoder {
+        ChunkedDecoder {
+            state: DecoderState::Size,
+            pos: 0,
+            body: Vec::new(),
+            trailers: Headers::new(),
+            trailers_size: 0,
+            framing_size: 0,
+            max_body_size,
+            max_trailers_size,
+        }
+    }
+
+    /// Returns how many bytes the encoded body took once the last chunk and the
+    /// trailers have arrived, or `Ok(None)` while more bytes are needed.
+    pub fn decode(&mut self, buf: &[u8]) -> Result<Option<usize>, HttpError> {
+        loop {
+            match self.state {
+                DecoderState::Size => {
+                    let line_end = match find_line_end(buf, self.pos)? {
+                        Some(line_end) => line_end,
+                        None if buf.len() - self.pos > MAX_CHUNK_SIZE_LINE => {
+                            return Err(HttpError::BadRequest(
+                                "chunk size line is too long".to_string(),
+                            ))
+                        }
+                        None => return Ok(None),
+                    };
+                    self.add_framing(line_end + 2 - self.pos)?;
+                    let size = parse_chunk_size(&buf[self.pos..line_end])?;
+                    self.pos = line_end + 2;
+                    if size == 0 {
+                        self.state = DecoderState::Trailers;
+                    } else if size > self.max_body_size - self.body.len() {
+                        return Err(HttpError::PayloadTooLarge(self.max_body_size));
+                    } else {
+                        self.state = DecoderState::Data(size);
+                    }
+                }
+                DecoderState::Data(remaining) => {
+                    let available = remaining.min(buf.len() - self.pos);
+                    if available == 0 {
+                        return Ok(None);
+                    }
+                    self.body
+                        .extend_from_slice(&buf[self.pos..self.pos + available]);
+                    self.pos += available;
+                    self.state = match remaining - available {
+                        0 => DecoderState::DataEnd,
+                        left => DecoderState::Data(left),
+                    };
+                }
+                DecoderState::DataEnd => {
+                    if buf.len() < self.pos + 2 {
+                        return Ok(None);
+                    }
+                    if &buf[self.pos..self.pos + 2] != b"\r\n" {
+                        return Err(HttpError::BadRequest(
+                            "chunk data is not followed by CRLF".to_string(),
+                        ));
+                    }
+                    self.add_framing(2)?;
+                    self.pos += 2;
+                    self.state = DecoderState::Size;
+                }
+                DecoderState::Trailers => {
+                    let line_end = match find_line_end(buf, self.pos)? {
+                        Some(line_end) => line_end,
+                        None if self.trailers_size + buf.len() - self.pos
+                            > self.max_trailers_size =>
+                        {
+                            return Err(HttpError::HeadersTooLarge(self.max_trailers_size))
+                        }
+                        None => return Ok(None),
+                    };
+                    self.trailers_size += line_end + 2 - self.pos;
+                    if self.trailers_size > self.max_trailers_size {
+                        return Err(HttpError::HeadersTooLarge(self.max_trailers_size));
+                    }
+                    let line = std::str::from_utf8(&buf[self.pos..line_end]).map_err(|_| {
+                        HttpError::BadRequest("trailer is not valid UTF-8".to_string())
+                    })?;
+                    self.pos = line_end + 2;
+                    if line.is_empty() {
+                        self.state = DecoderState::Done;
+                    } else {
+                        let (name, value) = parse_header_line(line)?;
+                        self.trailers.append(name, value);
+                    }
+                }
+                DecoderState::Done => return Ok(Some(self.pos)),
+            }
+        }
+    }
+
+    // Without a total, tiny chunks with long extensions would let a client send far
+    // more than 'max_body_size' for a single body.
+    fn add_framing(&mut self, len: usize) -> Result<(), HttpError> {
+        self.framing_size += len;
+        match self.framing_size > self.max_body_size.max(MIN_FRAMING_SIZE) {
+            true => Err(HttpError::PayloadTooLarge(self.max_body_size)),
+            false => Ok(()),
+        }
+    }
+
+    pub fn take_body(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.body)
+    }
+
+    pub fn take_trailers(&mut self) -> Headers {
+        std::mem::take(&mut self.trailers)
+    }
+}
+
+// Finds the CRLF ending the line that starts at `from`. Bare CR or LF are rejected,
+// the same as in the request head.
+fn find_line_end(buf: &[u8], from: usize) -> Result<Option<usize>, HttpError> {
+    for index in from..buf.len() {
+        match buf[index] {
+            b'\r' if index + 1 == buf.len() => return Ok(None),
+            b'\r' if buf[index + 1] == b'\n' => return Ok(Some(index)),
+            b'\r' | b'\n' => {
+                return Err(HttpError::BadRequest(
+                    "bare CR or LF in chunked body".to_string(),
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+// chunk-size [ chunk-ext ], where extensions are accepted but ignored.
+fn parse_chunk_size(line: &[u8]) -> Result<usize, HttpError> {
+    let size = match line.iter().position(|&b| b == b';') {
+        Some(semicolon) => &line[..semicolon],
+        None => line,
+    };
+    if size.is_empty() || size.len() > 16 || !size.iter().all(|b| b.is_ascii_hexdigit()) {
+        return Err(HttpError::BadRequest(format!(
+            "invalid chunk size '{}'",
+            String::from_utf8_lossy(size)
+        )));
+    }
+    let size = std::str::from_utf8(size).expect("hex digits are ASCII");
+    usize::from_str_radix(size, 16)
+        .map_err(|_| HttpError::BadRequest(format!("chunk size '{}' is too large", size)))
+}
+
+/// Frames `data` as a single chunk. Empty input produces no bytes at all,
+/// because a zero sized chunk would end the body.
+pub fn encode_chunk(data: &[u8]) -> Vec<u8> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let mut chunk = format!("{:X}\r\n", data.len()).into_bytes();
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(b"\r\n");
+    chunk
+}
+
+/// The last chunk, followed by optional trailer fields, that ends a chunked body.
+pub fn encode_last_chunk(trailers: &Headers) -> Vec<u8> {
+    let mut last = String::from("0\r\n");
+    for (name, value) in trailers.iter() {
+        last.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    last.push_str("\r\n");
+    last.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_across_partial_reads() {
+        let raw = b"4\r\nWiki\r\n6\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\n\r\n";
+        let mut decoder = ChunkedDecoder::new(1024, 1024);
+        for end in 0..raw.len() {
+            assert_eq!(decoder.decode(&raw[..end]).unwrap(), None);
+        }
+        assert_eq!(decoder.decode(raw).unwrap(), Some(raw.len()));
+        assert_eq!(decoder.take_body(), b"Wikipedia in \r\n\r\nchunks.");
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        let bad: [&[u8]; 4] = [
+            b"x\r\n",
+            b" 4\r\nWiki\r\n0\r\n\r\n",
+            b"4\r\nWikiXX0\r\n\r\n",
+            b"4\nWiki\r\n0\r\n\r\n",
+        ];
+        for raw in bad {
+            let mut decoder = ChunkedDecoder::new(1024, 1024);
+            assert!(matches!(decoder.decode(raw), Err(HttpError::BadRequest(_))));
+        }
+        let mut decoder = ChunkedDecoder::new(3, 1024);
+        assert_eq!(
+            decoder.decode(b"4\r\nWiki\r\n0\r\n\r\n"),
+            Err(HttpError::PayloadTooLarge(3))
+        );
+
+        // One byte of data per 1 KiB of extensions.
+        let chunk = format!("1;{}\r\na\r\n", "x".repeat(1000));
+        let raw = chunk.repeat(100);
+        let mut decoder = ChunkedDecoder::new(1024, 1024);
+        assert_eq!(
+            decoder.decode(raw.as_bytes()),
+            Err(HttpError::PayloadTooLarge(1024))
+        );
+    }
+
+    #[test]
+    fn encodes_chunks() {
+        let mut trailers = Headers::new();
+        trailers.append("Checksum", "abc");
+        let mut encoded = encode_chunk(b"hello, world");
+        encoded.extend(encode_chunk(b""));
+        encoded.extend(encode_last_chunk(&trailers));
+        assert_eq!(encoded, b"C\r\nhello, world\r\n0\r\nChecksum: abc\r\n\r\n");
+    }
+}
@@ -12,6 +12,7 @@ pub struct Config {
 pub enum ServerCommand {
     Help,
     Start,
+    Unlock,
     Version,
 }
 
@@ -21,6 +22,10 @@ pub enum ServerConfigArguments {
     Port,
     Tls,
     Verbose,
+    MaxHeaderSize,
+    MaxBodySize,
+    KeepAliveTimeout,
+    Username,
 }
 
 pub struct HelpMenu {}
@@ -33,19 +38,44 @@ impl HelpMenu {
         Commands:
           help              Show this help message and exit
           start             Start the web server
+          unlock USERNAME   Clear the failed logins and lockout of a user, needs DATABASE_URL
           version           Show program's version number and exit
           
         Options ('*' means mandatory):
           -ip               * Input IP address of the web server, e.g. '-ip 127.0.0.1'
           -p                * Input listening port of the web server, e.g. '-p 8080'
+          -maxheader        Maximum size in bytes of a request's headers, e.g. '-maxheader 8192'
+          -maxbody          Maximum size in bytes of a request's body, e.g. '-maxbody 1048576'
+          -keepalive        Seconds an idle connection is kept open, e.g. '-keepalive 5'
 
         Flags:
           --notls           Does not run TLS.
           --v, --verbose    Outputs a lot more info to the console!  
+
+        Environment (or a local .env file):
+          DATABASE_URL          PostgreSQL connection string, login is unavailable without it
+          DB_MAX_CONNECTIONS    Size of the connection pool, defaults to 10
+          DB_MIN_CONNECTIONS    Connections kept open while idle, defaults to 0
+          DB_ACQUIRE_TIMEOUT    Seconds to wait for a free connection, defaults to 3
+          ARGON2_MEMORY_KIB     Memory cost of password hashes, defaults to 19456
+          ARGON2_ITERATIONS     Time cost of password hashes, defaults to 2
+          ARGON2_PARALLELISM    Lanes used for password hashes, defaults to 1
+          COOKIE_KEYS           Base64 keys (32+ bytes) for signed and encrypted cookies,
+                                comma separated with the current one first
+          LOGIN_IP_BURST        Login attempts an address can make in a row, defaults to 10
+          LOGIN_IP_PER_MINUTE   Login attempts an address gets back every minute, defaults to 5
+          LOGIN_MAX_FAILURES    Failed logins before a user is locked, defaults to 5
+          LOGIN_FAILURE_WINDOW  Seconds after which failed logins are forgotten, defaults to 900
+          LOGIN_LOCKOUT         Seconds of the first lockout, doubled after every further
+                                failure, defaults to 60
+          LOGIN_MAX_LOCKOUT     Longest lockout in seconds, defaults to 3600
+          CORS_ORIGINS          Other origins allowed to call the server, comma separated,
+                                e.g. 'http://localhost:3000,https://*.example.com'
     
         Usage example:
           ironcladserver start -ip 127.0.0.1 -p 7878
           ironcladserver start -ip 127.0.0.1 -p 7878 --insecure
+          ironcladserver unlock mock1
           ironcladserver help
           ironcladserver version
         "#;
@@ -74,6 +104,7 @@ impl Config {
         let cli_command = match cli_input[1].to_lowercase().as_str() {
             "help" => ServerCommand::Help,
             "start" => ServerCommand::Start,
+            "unlock" => ServerCommand::Unlock,
             "version" => ServerCommand::Version,
             _ => return Err(ConfigError::UnknownCommand(cli_input[1].to_string())),
         };
@@ -86,6 +117,18 @@ impl Config {
             });
         }
 
+        if cli_command == ServerCommand::Unlock {
+            let Some(username) = cli_input.get(2) else {
+                return Err(ConfigError::MissingOption("USERNAME".to_string()));
+            };
+            args_opts_map.insert(ServerConfigArguments::Username, username.to_string());
+            return Ok(Config {
+                program: cli_program_name,
+                command: cli_command,
+                args_opts_map: Some(args_opts_map),
+            });
+        }
+
         Config::parse_args_opts(cli_input, &mut args_opts_map)?;
 
         if !args_opts_map.contains_key(&ServerConfigArguments::IpAddress) {
@@ -136,6 +179,45 @@ impl Config {
                         ));
                     }
                 }
+                "-maxheader" => {
+                    // max request head size
+                    if let std::collections::hash_map::Entry::Vacant(e) =
+                        args_opts_map.entry(ServerConfigArguments::MaxHeaderSize)
+                    {
+                        e.insert(option_value(cli_input, index, "-maxheader")?);
+                        index += 1;
+                    } else {
+                        return Err(ConfigError::ParseError(
+                            "option '-maxheader' is allowed once".to_string(),
+                        ));
+                    }
+                }
+                "-maxbody" => {
+                    // max request body size
+                    if let std::collections::hash_map::Entry::Vacant(e) =
+                        args_opts_map.entry(ServerConfigArguments::MaxBodySize)
+                    {
+                        e.insert(option_value(cli_input, index, "-maxbody")?);
+                        index += 1;
+                    } else {
+                        return Err(ConfigError::ParseError(
+                            "option '-maxbody' is allowed once".to_string(),
+                        ));
+                    }
+                }
+                "-keepalive" => {
+                    // idle connection timeout
+                    if let std::collections::hash_map::Entry::Vacant(e) =
+                        args_opts_map.entry(ServerConfigArguments::KeepAliveTimeout)
+                    {
+                        e.insert(option_value(cli_input, index, "-keepalive")?);
+                        index += 1;
+                    } else {
+                        return Err(ConfigError::ParseError(
+                            "option '-keepalive' is allowed once".to_string(),
+                        ));
+                    }
+                }
                 "--notls" => {
                     // tls bool
                     if let std::collections::hash_map::Entry::Vacant(e) =
@@ -172,6 +254,14 @@ impl Config {
     }
 }
 
+// The value after the option at 'index', an error when the option is the last argument.
+fn option_value(cli_input: &[String], index: usize, option: &str) -> Result<String, ConfigError> {
+    cli_input
+        .get(index + 1)
+        .cloned()
+        .ok_or_else(|| ConfigError::ParseError(format!("option '{}' needs a value", option)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -207,4 +297,29 @@ mod tests {
             Err(e) => panic!("Error: {}.", e),
         }
     }
+
+    #[test]
+    fn parses_unlock_command() {
+        let cli_input: Vec<String> = ["ironcladserver", "unlock", "mock1"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let config = Config::build(&cli_input).unwrap();
+        assert_eq!(config.command, ServerCommand::Unlock);
+        let args_opts_map = config.args_opts_map.unwrap();
+        assert_eq!(args_opts_map[&ServerConfigArguments::Username], "mock1");
+        assert!(Config::build(&cli_input[..2]).is_err());
+    }
+
+    #[test]
+    fn rejects_options_without_values() {
+        for option in ["-maxheader", "-maxbody", "-keepalive"] {
+            let cli_input: Vec<String> = ["ironcladserver", "start", option]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect();
+            let mut args_opts_map = HashMap::new();
+            assert!(Config::parse_args_opts(&cli_input, &mut args_opts_map).is_err());
+        }
+    }
 }
@@ -0,0 +1,473 @@
+#![forbid(unsafe_code)]
+
+use crate::middleware::{Middleware, Next};
+use crate::request::Request;
+use crate::response::{Body, Response};
+use crate::status::StatusCode;
+use bytes::Bytes;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use futures::future::BoxFuture;
+use std::io::Write;
+use tokio::io::AsyncReadExt;
+
+/// Content codings we can produce, in the order we prefer them when the client
+/// likes them equally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Extension of a precompressed sibling file, e.g. 'site.css.br'.
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+            Encoding::Deflate => "zz",
+        }
+    }
+
+    fn encode(self, input: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut output = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut output, 4096, 5, 22);
+                    writer.write_all(input)?;
+                }
+                Ok(output)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(input)?;
+                encoder.finish()
+            }
+            // 'deflate' in HTTP is the zlib format (RFC 9110, 8.4.1.2), not raw deflate.
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(input)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Picks the coding to use from an `Accept-Encoding` value, None meaning identity.
+/// Codings with a higher q-value win, ties go to the order of 'available'.
+pub fn negotiate(accept_encoding: Option<&str>, available: &[Encoding]) -> Option<Encoding> {
+    let accept_encoding = accept_encoding?;
+    let mut preferences: Vec<(String, f32)> = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        if coding.is_empty() {
+            continue;
+        }
+        let mut quality = 1.0;
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    quality = value.trim().parse().unwrap_or(0.0);
+                }
+            }
+        }
+        preferences.push((coding, quality));
+    }
+    let quality_of = |encoding: Encoding| {
+        let find = |name: &str| {
+            preferences
+                .iter()
+                .find(|(coding, _)| coding == name)
+                .map(|(_, quality)| *quality)
+        };
+        match encoding {
+            // 'x-gzip' is an old alias that some clients still send.
+            Encoding::Gzip => find("gzip").or_else(|| find("x-gzip")),
+            _ => find(encoding.as_str()),
+        }
+        .or_else(|| find("*"))
+        .unwrap_or(0.0)
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for &encoding in available {
+        let quality = quality_of(encoding);
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Whether a media type is worth compressing. Images, video and archives already are.
+pub fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+                | "image/x-icon"
+        )
+}
+
+/// Adds a token to `Vary` unless it is already listed.
+pub(crate) fn add_vary(response: &mut Response, token: &str) {
+    if !response.headers.has_token("Vary", token) {
+        response.headers.append("Vary", token);
+    }
+}
+
+/// Compresses response bodies with the best coding the client accepts.
+///
+/// Only complete 200 responses of a compressible type and at least `min_size` bytes are
+/// compressed. A response carrying `Cache-Control: no-transform` is left alone, which is how
+/// handlers opt out (see `ResponseBuilder::no_compression`): compressing secrets together with
+/// text the attacker controls leaks them through the compressed size (BREACH).
+pub struct Compression {
+    min_size: usize,
+    max_file_size: u64,
+    encodings: Vec<Encoding>,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression {
+            min_size: 1024,
+            max_file_size: 8 * 1024 * 1024,
+            encodings: vec![Encoding::Brotli, Encoding::Gzip, Encoding::Deflate],
+        }
+    }
+
+    /// Bodies smaller than this aren't worth the CPU, the headers alone are a few hundred bytes.
+    pub fn min_size(mut self, min_size: usize) -> Compression {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Files larger than this are sent as they are instead of being compressed in memory.
+    pub fn max_file_size(mut self, max_file_size: u64) -> Compression {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// The codings to offer, most preferred first.
+    pub fn encodings(mut self, encodings: &[Encoding]) -> Compression {
+        self.encodings = encodings.to_vec();
+        self
+    }
+
+    async fn compress(&self, accept_encoding: Option<String>, mut response: Response) -> Response {
+        let eligible = response.status == StatusCode::Ok
+            && !response.headers.contains("Content-Encoding")
+            && !response.headers.contains("Content-Range")
+            && !response.headers.has_token("Cache-Control", "no-transform")
+            && response.header("Content-Type").is_some_and(is_compressible);
+        let len = match &response.body {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File { len, .. } if *len <= self.max_file_size => *len,
+            _ => return response,
+        };
+        if !eligible || len < self.min_size as u64 {
+            return response;
+        }
+        // From here on the representation depends on Accept-Encoding, whatever we pick.
+        add_vary(&mut response, "Accept-Encoding");
+        let Some(encoding) = negotiate(accept_encoding.as_deref(), &self.encodings) else {
+            return response;
+        };
+
+        let content = match std::mem::replace(&mut response.body, Body::Empty) {
+            Body::Bytes(bytes) => bytes,
+            Body::File { file, len } => {
+                let mut content = Vec::with_capacity(len as usize);
+                if let Err(e) = file.take(len).read_to_end(&mut content).await {
+                    eprintln!("Error reading file for compression: {}", e);
+                    return Response::new(StatusCode::InternalServerError);
+                }
+                Bytes::from(content)
+            }
+            _ => unreachable!("only complete bodies are compressed"),
+        };
+        let original = content.clone();
+        let compressed = match tokio::task::spawn_blocking(move || encoding.encode(&content)).await
+        {
+            Ok(Ok(compressed)) => compressed,
+            Ok(Err(e)) => {
+                eprintln!("Error compressing response: {}", e);
+                response.body = Body::Bytes(original);
+                return response;
+            }
+            Err(e) => {
+                eprintln!("Compression task failed: {}", e);
+                response.body = Body::Bytes(original);
+                return response;
+            }
+        };
+        if compressed.len() >= original.len() {
+            response.body = Body::Bytes(original);
+            return response;
+        }
+
+        response
+            .headers
+            .insert("Content-Encoding", encoding.as_str());
+        // A different representation needs a different strong validator.
+        if let Some(etag) = response.header("ETag").map(str::to_string) {
+            response
+                .headers
+                .insert("ETag", encoded_etag(&etag, encoding));
+        }
+        response.body = Body::Bytes(Bytes::from(compressed));
+        response
+    }
+
+    // A 304 carries the ETag of the variant the client already has, named by the tag it
+    // matched. When it sent tags of several variants, the one we would send now wins.
+    fn not_modified_etag(
+        &self,
+        accept_encoding: Option<&str>,
+        sent_tags: &[(String, Option<Encoding>)],
+        response: &mut Response,
+    ) {
+        let Some(etag) = response.header("ETag").map(str::to_string) else {
+            return;
+        };
+        let opaque = |tag: &str| tag.trim_start_matches("W/").to_string();
+        let held: Vec<Option<Encoding>> = sent_tags
+            .iter()
+            .filter(|(tag, _)| opaque(tag) == opaque(&etag))
+            .map(|(_, encoding)| *encoding)
+            .collect();
+        let preferred = negotiate(accept_encoding, &self.encodings);
+        let variant = match held.contains(&preferred) {
+            true => preferred,
+            false => held.first().copied().flatten(),
+        };
+        if let Some(encoding) = variant {
+            response
+                .headers
+                .insert("ETag", encoded_etag(&etag, encoding));
+            add_vary(response, "Accept-Encoding");
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle<'a>(&'a self, mut request: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let accept_encoding = request.header("Accept-Encoding").map(str::to_string);
+            // Map the tags of compressed variants back, so handlers can revalidate them.
+            let sent_tags: Vec<(String, Option<Encoding>)> = request
+                .headers
+                .get_all("If-None-Match")
+                .flat_map(|value| value.split(','))
+                .map(decode_etag)
+                .collect();
+            if !sent_tags.is_empty() {
+                let tags: Vec<&str> = sent_tags.iter().map(|(tag, _)| tag.as_str()).collect();
+                request.headers.insert("If-None-Match", tags.join(", "));
+            }
+            let mut response = next.run(request).await;
+            if response.status == StatusCode::NotModified {
+                self.not_modified_etag(accept_encoding.as_deref(), &sent_tags, &mut response);
+                return response;
+            }
+            self.compress(accept_encoding, response).await
+        })
+    }
+}
+
+// '"abc"' becomes '"abc-gzip"'.
+fn encoded_etag(etag: &str, encoding: Encoding) -> String {
+    match etag.strip_suffix('"') {
+        Some(open) => format!("{}-{}\"", open, encoding.as_str()),
+        None => etag.to_string(),
+    }
+}
+
+// '"abc-gzip"' becomes '"abc"' and the coding it was made for.
+fn decode_etag(tag: &str) -> (String, Option<Encoding>) {
+    let tag = tag.trim();
+    [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate]
+        .into_iter()
+        .find_map(|encoding| {
+            tag.strip_suffix(&format!("-{}\"", encoding.as_str()))
+                .map(|open| (format!("{}\"", open), Some(encoding)))
+        })
+        .unwrap_or_else(|| (tag.to_string(), None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::Handler;
+    use crate::middleware::HandlerExt;
+    use crate::request::RequestParser;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    #[test]
+    fn negotiates_encodings() {
+        assert_eq!(negotiate(None, &ALL), None);
+        assert_eq!(
+            negotiate(Some("gzip, deflate, br"), &ALL),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(
+            negotiate(Some("gzip;q=1, br;q=0.5"), &ALL),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate(Some("br;q=0, *"), &ALL), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("identity"), &ALL), None);
+        assert_eq!(negotiate(Some("*;q=0"), &ALL), None);
+        assert_eq!(negotiate(Some("x-gzip"), &ALL), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("br"), &[Encoding::Gzip]), None);
+    }
+
+    fn request(headers: &str) -> Request {
+        let raw = format!("GET / HTTP/1.1\r\nHost: test\r\n{}\r\n", headers);
+        RequestParser::new()
+            .parse(raw.as_bytes())
+            .unwrap()
+            .unwrap()
+            .0
+    }
+
+    fn page(_request: Request) -> futures::future::Ready<Response> {
+        let mut response = Response::html(StatusCode::Ok, "<p>hello</p>".repeat(200));
+        response.headers.insert("ETag", "\"abc\"");
+        futures::future::ready(response)
+    }
+
+    #[tokio::test]
+    async fn compresses_eligible_responses() {
+        let app = page.layer(Compression::new());
+        let response = app.call(request("Accept-Encoding: gzip\r\n")).await;
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.header("ETag"), Some("\"abc-gzip\""));
+        let Body::Bytes(compressed) = response.body else {
+            panic!("expected a compressed body");
+        };
+        let mut decoded = String::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "<p>hello</p>".repeat(200));
+
+        let response = app.call(request("")).await;
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            decode_etag(" W/\"abc-br\""),
+            ("W/\"abc\"".to_string(), Some(Encoding::Brotli))
+        );
+    }
+
+    #[tokio::test]
+    async fn tags_not_modified_responses_with_the_held_variant() {
+        let app = (|request: Request| {
+            let fresh = request
+                .header("If-None-Match")
+                .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == "\"abc\""));
+            match fresh {
+                true => futures::future::ready(
+                    Response::builder(StatusCode::NotModified)
+                        .header("ETag", "\"abc\"")
+                        .empty(),
+                ),
+                false => page(request),
+            }
+        })
+        .layer(Compression::new());
+
+        for (headers, etag) in [
+            (
+                "Accept-Encoding: gzip\r\nIf-None-Match: \"abc-gzip\"\r\n",
+                "\"abc-gzip\"",
+            ),
+            (
+                "Accept-Encoding: gzip\r\nIf-None-Match: \"abc-br\"\r\n",
+                "\"abc-br\"",
+            ),
+            (
+                "Accept-Encoding: gzip\r\nIf-None-Match: \"abc-br\", W/\"abc-gzip\"\r\n",
+                "\"abc-gzip\"",
+            ),
+            (
+                "Accept-Encoding: gzip\r\nIf-None-Match: \"abc\"\r\n",
+                "\"abc\"",
+            ),
+        ] {
+            let response = app.call(request(headers)).await;
+            assert_eq!(response.status, StatusCode::NotModified, "{}", headers);
+            assert_eq!(response.header("ETag"), Some(etag), "{}", headers);
+        }
+        let response = app
+            .call(request(
+                "Accept-Encoding: gzip\r\nIf-None-Match: \"abc-deflate\"\r\n",
+            ))
+            .await;
+        assert_eq!(response.header("ETag"), Some("\"abc-deflate\""));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        let response = app.call(request("If-None-Match: \"other\"\r\n")).await;
+        assert_eq!(response.status, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn skips_opted_out_small_and_binary_responses() {
+        let secret = (|_| async {
+            Response::builder(StatusCode::Ok)
+                .content_type("text/html")
+                .no_compression()
+                .body("<p>token</p>".repeat(200))
+        })
+        .layer(Compression::new());
+        let small =
+            (|_| async { Response::html(StatusCode::Ok, "<p>hi</p>") }).layer(Compression::new());
+        let image = (|_| async {
+            Response::builder(StatusCode::Ok)
+                .content_type("image/png")
+                .body(vec![0; 4096])
+        })
+        .layer(Compression::new());
+
+        for app in [
+            Box::new(secret) as Box<dyn Handler>,
+            Box::new(small),
+            Box::new(image),
+        ] {
+            let response = app.call(request("Accept-Encoding: gzip, br\r\n")).await;
+            assert_eq!(response.header("Content-Encoding"), None);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+#![forbid(unsafe_code)]
+
+use crate::request::{Method, Request};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// IMF-fixdate, the only format we send (RFC 9110, 5.6.7).
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Formats a time as an HTTP date, e.g. 'Sun, 06 Nov 1994 08:49:37 GMT'.
+pub fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format(HTTP_DATE_FORMAT)
+        .to_string()
+}
+
+/// Parses an HTTP date in IMF-fixdate, or the obsolete RFC 850 and asctime formats
+/// that recipients still have to accept.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let parsed = NaiveDateTime::parse_from_str(value, HTTP_DATE_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%A, %d-%b-%y %H:%M:%S GMT"))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%a %b %e %H:%M:%S %Y"))
+        .ok()?;
+    Some(SystemTime::from(parsed.and_utc()))
+}
+
+/// An entity tag as sent in `ETag` and the conditional request headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityTag {
+    pub weak: bool,
+    /// The opaque tag without quotes.
+    pub tag: String,
+}
+
+impl EntityTag {
+    pub fn strong(tag: impl Into<String>) -> EntityTag {
+        EntityTag {
+            weak: false,
+            tag: tag.into(),
+        }
+    }
+
+    /// A strong tag from a content hash, keeping the first 128 bits of it.
+    pub fn from_digest(digest: &[u8]) -> EntityTag {
+        EntityTag::strong(hex::encode(&digest[..digest.len().min(16)]))
+    }
+
+    /// A strong tag from what changes whenever a file does: its path, size, modification
+    /// time to the nanosecond and, on Unix, its inode. Unlike a content hash it doesn't
+    /// need the file read, which matters for Range requests into big files.
+    pub fn from_metadata(path: &Path, metadata: &Metadata) -> EntityTag {
+        let mut hasher = Sha256::new();
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.update(metadata.len().to_be_bytes());
+        if let Some(since_epoch) = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        {
+            hasher.update(since_epoch.as_nanos().to_be_bytes());
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            hasher.update(metadata.dev().to_be_bytes());
+            hasher.update(metadata.ino().to_be_bytes());
+        }
+        EntityTag::from_digest(&hasher.finalize())
+    }
+
+    pub fn parse(value: &str) -> Option<EntityTag> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        if tag.contains('"') {
+            return None;
+        }
+        Some(EntityTag {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+
+    /// Weak comparison, used for `If-None-Match`: the tags match, weak or not.
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+
+    /// Strong comparison, used for `If-Match` and `If-Range`: both have to be strong.
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+}
+
+impl std::fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.weak {
+            true => write!(f, "W/\"{}\"", self.tag),
+            false => write!(f, "\"{}\"", self.tag),
+        }
+    }
+}
+
+/// Whether a GET or HEAD can be answered with 304 Not Modified (RFC 9110, 13.2.2).
+/// `If-None-Match` takes precedence, `If-Modified-Since` is only looked at without it.
+pub fn is_not_modified(
+    request: &Request,
+    etag: Option<&EntityTag>,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    if !matches!(request.method, Method::Get | Method::Head) {
+        return false;
+    }
+    if request.headers.contains("If-None-Match") {
+        let Some(etag) = etag else {
+            return false;
+        };
+        return request
+            .headers
+            .get_all("If-None-Match")
+            .flat_map(|value| value.split(','))
+            .any(|candidate| {
+                candidate.trim() == "*"
+                    || EntityTag::parse(candidate).is_some_and(|tag| tag.weak_eq(etag))
+            });
+    }
+    match (request.header("If-Modified-Since"), last_modified) {
+        (Some(since), Some(last_modified)) => match parse_http_date(since) {
+            Some(since) => truncate_to_seconds(last_modified) <= since,
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+// HTTP dates only have second precision, file times usually have more.
+pub(crate) fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => UNIX_EPOCH + std::time::Duration::from_secs(since_epoch.as_secs()),
+        Err(_) => time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::RequestParser;
+    use std::time::Duration;
+
+    fn request(method: &str, headers: &str) -> Request {
+        let raw = format!("{} / HTTP/1.1\r\nHost: test\r\n{}\r\n", method, headers);
+        RequestParser::new()
+            .parse(raw.as_bytes())
+            .unwrap()
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn formats_and_parses_http_dates() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        for value in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse_http_date(value), Some(time), "{}", value);
+        }
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn evaluates_preconditions() {
+        let etag = EntityTag::strong("abc");
+        let modified = UNIX_EPOCH + Duration::from_millis(784_111_777_500);
+        let check = |method: &str, headers: &str| {
+            is_not_modified(&request(method, headers), Some(&etag), Some(modified))
+        };
+
+        assert!(check("GET", "If-None-Match: \"x\", W/\"abc\"\r\n"));
+        assert!(check("HEAD", "If-None-Match: *\r\n"));
+        assert!(!check("GET", "If-None-Match: \"x\"\r\n"));
+        assert!(!check("POST", "If-None-Match: \"abc\"\r\n"));
+        assert!(check(
+            "GET",
+            "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n"
+        ));
+        assert!(!check(
+            "GET",
+            "If-Modified-Since: Sun, 06 Nov 1994 08:49:36 GMT\r\n"
+        ));
+        // If-None-Match wins even when the date alone would allow a 304.
+        assert!(!check(
+            "GET",
+            "If-None-Match: \"x\"\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n"
+        ));
+        assert!(!check("GET", ""));
+        assert!(EntityTag::parse("W/\"abc\"").is_some_and(|tag| !tag.strong_eq(&etag)));
+    }
+}
@@ -0,0 +1,526 @@
+#![forbid(unsafe_code)]
+
+use crate::conditional::http_date;
+use crate::error::{ConfigError, CookieError};
+use crate::request::Request;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::env;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie to send in `Set-Cookie` (RFC 6265, 4.1).
+///
+/// ```ignore
+/// let theme = Cookie::new("theme", "dark")?
+///     .path("/")
+///     .max_age(Duration::from_secs(86400))
+///     .same_site(SameSite::Lax);
+/// Response::builder(StatusCode::Ok).cookie(&theme).body("...")
+/// ```
+///
+/// Names starting with '__Host-' or '__Secure-' start out with the attributes browsers
+/// require for them: `Secure`, and for '__Host-' also `Path=/` and never a `Domain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Fails unless 'name' is a token and 'value' only has cookie-octets, i.e. printable
+    /// ASCII without spaces, '"', ',', ';' and '\'. Encode anything else first.
+    pub fn new(name: &str, value: &str) -> Result<Cookie, CookieError> {
+        if name.is_empty() || !name.bytes().all(is_token_byte) {
+            return Err(CookieError::InvalidName(name.to_string()));
+        }
+        if !value.bytes().all(is_cookie_octet) {
+            return Err(CookieError::InvalidValue(name.to_string()));
+        }
+        let host_only = name.starts_with("__Host-");
+        Ok(Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            max_age: None,
+            expires: None,
+            domain: None,
+            path: host_only.then(|| "/".to_string()),
+            secure: host_only || name.starts_with("__Secure-"),
+            http_only: false,
+            same_site: None,
+        })
+    }
+
+    /// A cookie that makes the browser delete 'name'. Path and domain have to match
+    /// the ones the cookie was set with.
+    pub fn removal(name: &str) -> Result<Cookie, CookieError> {
+        Ok(Cookie::new(name, "")?
+            .max_age(Duration::ZERO)
+            .expires(SystemTime::UNIX_EPOCH))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Cookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Ignored for '__Host-' cookies. Characters that would end the attribute are dropped.
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        self.domain = Some(attribute_value(domain));
+        self
+    }
+
+    /// Characters that would end the attribute are dropped.
+    pub fn path(mut self, path: &str) -> Cookie {
+        self.path = Some(attribute_value(path));
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+/// The `Set-Cookie` field value.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+        if let Some(domain) = self
+            .domain
+            .as_ref()
+            .filter(|_| !self.name.starts_with("__Host-"))
+        {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+// tchar from RFC 9110, 5.6.2.
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+// cookie-octet from RFC 6265, 4.1.1.
+fn is_cookie_octet(b: u8) -> bool {
+    matches!(b, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+fn attribute_value(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| *c != ';' && !c.is_control())
+        .collect()
+}
+
+/// The cookies a request came with, from its `Cookie` header(s) (RFC 6265, 5.4).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+    pub fn from_request(request: &Request) -> CookieJar {
+        let mut jar = CookieJar::default();
+        for value in request.headers.get_all("Cookie") {
+            jar.add_header(value);
+        }
+        jar
+    }
+
+    pub fn parse(header: &str) -> CookieJar {
+        let mut jar = CookieJar::default();
+        jar.add_header(header);
+        jar
+    }
+
+    // Pairs without a '=' or a name are skipped rather than failing the whole header,
+    // browsers send whatever other sites managed to set.
+    fn add_header(&mut self, header: &str) {
+        for pair in header.split(';') {
+            let Some((name, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let value = value.trim();
+            let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                Some(unquoted) => unquoted,
+                None => value,
+            };
+            self.cookies.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    /// The value of cookie 'name'. When there are several, browsers send the one with the
+    /// most specific path first, so that is the one returned.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(cookie, _)| cookie == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of a cookie made with `CookieKeys::sign`, None if it is missing or
+    /// was tampered with.
+    pub fn get_signed(&self, name: &str, keys: &CookieKeys) -> Option<String> {
+        keys.verify(name, self.get(name)?, None)
+    }
+
+    /// The value of a cookie made with `CookieKeys::sign_bound` for the same 'context',
+    /// None if it is missing, was tampered with or was made for another context.
+    pub fn get_signed_bound(&self, name: &str, keys: &CookieKeys, context: &str) -> Option<String> {
+        keys.verify(name, self.get(name)?, Some(context))
+    }
+
+    /// The value of a cookie made with `CookieKeys::encrypt`, None if it is missing,
+    /// was tampered with or can't be decrypted.
+    pub fn get_encrypted(&self, name: &str, keys: &CookieKeys) -> Option<String> {
+        keys.decrypt(name, self.get(name)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+}
+
+// Separate keys for signing and encrypting, both derived from one master key.
+#[derive(Clone)]
+struct DerivedKeys {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl DerivedKeys {
+    fn new(master: &[u8]) -> DerivedKeys {
+        let derive = |purpose: &[u8]| -> [u8; 32] {
+            let mut mac =
+                <HmacSha256 as Mac>::new_from_slice(master).expect("HMAC takes keys of any size");
+            mac.update(purpose);
+            mac.finalize().into_bytes().into()
+        };
+        DerivedKeys {
+            signing: derive(b"ironclad cookie signing"),
+            encryption: derive(b"ironclad cookie encryption"),
+        }
+    }
+
+    fn mac(&self, name: &str, value: &str, context: Option<&str>) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.signing)
+            .expect("HMAC takes keys of any size");
+        // The name is covered too, so a value can't be moved to another cookie.
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        // Cookie values never contain NUL, so value and context can't run into each other.
+        if let Some(context) = context {
+            mac.update(b"\0");
+            mac.update(context.as_bytes());
+        }
+        mac
+    }
+}
+
+/// Keys for signed (HMAC-SHA256) and encrypted (AES-256-GCM) cookies.
+///
+/// The first key makes new cookies, the others are still accepted when reading them.
+/// To rotate, put a new key in front and drop the oldest once the cookies it made
+/// have expired. The server reads them from 'COOKIE_KEYS' into its state.
+#[derive(Clone)]
+pub struct CookieKeys {
+    keys: Vec<DerivedKeys>,
+}
+
+impl CookieKeys {
+    pub const MIN_KEY_LEN: usize = 32;
+
+    /// 'current' signs and encrypts, 'previous' only verify and decrypt. Every key
+    /// needs at least 32 bytes.
+    pub fn new(current: &[u8], previous: &[&[u8]]) -> Result<CookieKeys, CookieError> {
+        let mut keys = Vec::with_capacity(previous.len() + 1);
+        for key in [current].iter().chain(previous) {
+            if key.len() < CookieKeys::MIN_KEY_LEN {
+                return Err(CookieError::InvalidKey(format!(
+                    "keys need at least {} bytes, got {}",
+                    CookieKeys::MIN_KEY_LEN,
+                    key.len()
+                )));
+            }
+            keys.push(DerivedKeys::new(key));
+        }
+        Ok(CookieKeys { keys })
+    }
+
+    /// A random key. Cookies made with it can't be read after a restart.
+    pub fn generate() -> CookieKeys {
+        CookieKeys {
+            keys: vec![DerivedKeys::new(&rand::random::<[u8; 32]>())],
+        }
+    }
+
+    /// Reads 'COOKIE_KEYS', a comma separated list of base64 keys with the current one
+    /// first. None when it isn't set.
+    pub fn from_env() -> Result<Option<CookieKeys>, ConfigError> {
+        let Ok(value) = env::var("COOKIE_KEYS") else {
+            return Ok(None);
+        };
+        let invalid =
+            |reason: String| ConfigError::ParseError(format!("'COOKIE_KEYS': {}", reason));
+        let keys = value
+            .split(',')
+            .map(|key| STANDARD.decode(key.trim()))
+            .collect::<Result<Vec<Vec<u8>>, _>>()
+            .map_err(|e| invalid(e.to_string()))?;
+        let previous: Vec<&[u8]> = keys.iter().skip(1).map(Vec::as_slice).collect();
+        CookieKeys::new(&keys[0], &previous)
+            .map(Some)
+            .map_err(|e| invalid(e.to_string()))
+    }
+
+    /// The same cookie with its value signed. Clients can still read the value, but
+    /// not change it.
+    pub fn sign(&self, cookie: Cookie) -> Cookie {
+        self.sign_with(cookie, None)
+    }
+
+    /// Like `sign`, but the signature also covers 'context', e.g. a session id, which
+    /// isn't put in the cookie. The cookie only verifies for the same context.
+    pub fn sign_bound(&self, cookie: Cookie, context: &str) -> Cookie {
+        self.sign_with(cookie, Some(context))
+    }
+
+    fn sign_with(&self, mut cookie: Cookie, context: Option<&str>) -> Cookie {
+        let tag = self.keys[0]
+            .mac(&cookie.name, &cookie.value, context)
+            .finalize();
+        cookie.value = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(tag.into_bytes()),
+            cookie.value
+        );
+        cookie
+    }
+
+    /// The same cookie with its value encrypted, so clients can neither read nor change it.
+    pub fn encrypt(&self, mut cookie: Cookie) -> Cookie {
+        let cipher = Aes256Gcm::new(&self.keys[0].encryption.into());
+        let nonce: [u8; 12] = rand::random();
+        let payload = Payload {
+            msg: cookie.value.as_bytes(),
+            aad: cookie.name.as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .expect("cookie values are far below the AES-GCM size limit");
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        cookie.value = URL_SAFE_NO_PAD.encode(sealed);
+        cookie
+    }
+
+    fn verify(&self, name: &str, signed: &str, context: Option<&str>) -> Option<String> {
+        let (tag, value) = signed.split_once('.')?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+        self.keys
+            .iter()
+            // verify_slice compares in constant time.
+            .any(|key| key.mac(name, value, context).verify_slice(&tag).is_ok())
+            .then(|| value.to_string())
+    }
+
+    fn decrypt(&self, name: &str, sealed: &str) -> Option<String> {
+        let sealed = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+        if sealed.len() < 12 {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        self.keys.iter().find_map(|key| {
+            let cipher = Aes256Gcm::new(&key.encryption.into());
+            let payload = Payload {
+                msg: ciphertext,
+                aad: name.as_bytes(),
+            };
+            let plaintext = cipher.decrypt(Nonce::from_slice(nonce), payload).ok()?;
+            String::from_utf8(plaintext).ok()
+        })
+    }
+}
+
+// Never print key material.
+impl fmt::Debug for CookieKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CookieKeys({} keys)", self.keys.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cookie_headers() {
+        let jar = CookieJar::parse("a=1; b=\"two\";c=;  =skipped; junk; a=shadowed; d=x=y");
+        assert_eq!(jar.get("a"), Some("1"));
+        assert_eq!(jar.get("b"), Some("two"));
+        assert_eq!(jar.get("c"), Some(""));
+        assert_eq!(jar.get("d"), Some("x=y"));
+        assert_eq!(jar.get("junk"), None);
+        assert_eq!(jar.len(), 5);
+    }
+
+    #[test]
+    fn formats_set_cookie() {
+        let cookie = Cookie::new("theme", "dark")
+            .unwrap()
+            .domain("example.com;evil")
+            .path("/app")
+            .max_age(Duration::from_secs(60))
+            .expires(SystemTime::UNIX_EPOCH + Duration::from_secs(784111777))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Strict);
+        assert_eq!(
+            cookie.to_string(),
+            "theme=dark; Max-Age=60; Expires=Sun, 06 Nov 1994 08:49:37 GMT; \
+            Domain=example.comevil; Path=/app; Secure; HttpOnly; SameSite=Strict"
+        );
+
+        let host = Cookie::new("__Host-id", "1").unwrap().domain("example.com");
+        assert_eq!(host.to_string(), "__Host-id=1; Path=/; Secure");
+        assert!(Cookie::removal("id")
+            .unwrap()
+            .to_string()
+            .starts_with("id=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"));
+
+        assert!(Cookie::new("a b", "1").is_err());
+        assert!(Cookie::new("a", "1;\r\nSet-Cookie: x=y").is_err());
+        assert!(Cookie::new("a", "\"1\"").is_err());
+    }
+
+    #[test]
+    fn signs_and_encrypts_with_key_rotation() {
+        let old_key = [1u8; 32];
+        let new_key = [2u8; 32];
+        let old = CookieKeys::new(&old_key, &[]).unwrap();
+        let rotated = CookieKeys::new(&new_key, &[&old_key]).unwrap();
+        assert!(CookieKeys::new(&[0; 16], &[]).is_err());
+
+        let signed = old.sign(Cookie::new("user", "mock1").unwrap());
+        let jar = CookieJar::parse(&signed.to_string());
+        assert_eq!(jar.get_signed("user", &rotated), Some("mock1".to_string()));
+        let forged = signed.value().replace("mock1", "admin");
+        let jar = CookieJar::parse(&format!("user={}; other={}", forged, signed.value()));
+        assert_eq!(jar.get_signed("user", &rotated), None);
+        assert_eq!(jar.get_signed("other", &rotated), None);
+
+        let bound = old.sign_bound(Cookie::new("token", "abc").unwrap(), "session-1");
+        let jar = CookieJar::parse(&bound.to_string());
+        assert_eq!(
+            jar.get_signed_bound("token", &rotated, "session-1"),
+            Some("abc".to_string())
+        );
+        assert_eq!(jar.get_signed_bound("token", &rotated, "session-2"), None);
+        assert_eq!(jar.get_signed("token", &rotated), None);
+
+        let encrypted = old.encrypt(Cookie::new("cart", "3-apples").unwrap());
+        assert!(!encrypted.value().contains("apples"));
+        let jar = CookieJar::parse(&format!(
+            "cart={v}; moved={v}; bad=AAAA",
+            v = encrypted.value()
+        ));
+        assert_eq!(
+            jar.get_encrypted("cart", &rotated),
+            Some("3-apples".to_string())
+        );
+        assert_eq!(jar.get_encrypted("moved", &rotated), None);
+        assert_eq!(jar.get_encrypted("bad", &rotated), None);
+        assert_eq!(
+            jar.get_encrypted("cart", &CookieKeys::new(&new_key, &[]).unwrap()),
+            None
+        );
+    }
+}
@@ -0,0 +1,516 @@
+#![forbid(unsafe_code)]
+
+use crate::compression::add_vary;
+use crate::middleware::{Middleware, Next};
+use crate::request::{Method, Request};
+use crate::response::Response;
+use crate::status::StatusCode;
+use futures::future::BoxFuture;
+use std::time::Duration;
+
+/// Lets pages from other origins call the server (cross-origin resource sharing).
+/// Nothing is allowed until origins are added, either exactly such as
+/// 'https://app.example.com', or as patterns such as 'http://localhost:*' or
+/// 'https://*.example.com' (see `OriginPattern`). '*' alone allows every origin, but
+/// not together with credentials.
+///
+/// Preflight `OPTIONS` requests are answered here without reaching the handler, with
+/// 204 when the origin, method and headers are allowed and 403 otherwise. Other
+/// responses to an allowed origin get `Access-Control-Allow-Origin` set to that origin.
+/// All responses get `Vary: Origin`, since they differ between origins.
+///
+/// ```ignore
+/// let cors = Cors::new()
+///     .allow_origin("http://localhost:*")
+///     .allow_headers(&["Content-Type", "X-CSRF-Token"])
+///     .allow_credentials(true)
+///     .max_age(Duration::from_secs(600));
+/// let app = router.layer(cors);
+/// ```
+pub struct Cors {
+    origins: Vec<OriginPattern>,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl Cors {
+    /// No origins, the methods GET, HEAD and POST, no extra headers and no credentials.
+    pub fn new() -> Cors {
+        Cors {
+            origins: Vec::new(),
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            headers: Vec::new(),
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// An origin or origin pattern allowed to make requests.
+    ///
+    /// Panics if it isn't an origin or pattern, or if it is '*' while credentials are
+    /// allowed.
+    pub fn allow_origin(mut self, origin: &str) -> Cors {
+        let pattern = OriginPattern::parse(origin).unwrap_or_else(|err| panic!("{}", err));
+        if pattern == OriginPattern::Any && self.credentials {
+            panic!("CORS can't allow every origin ('*') with credentials");
+        }
+        self.origins.push(pattern);
+        self
+    }
+
+    /// The methods allowed for cross-origin requests, replacing the defaults.
+    pub fn allow_methods(mut self, methods: &[Method]) -> Cors {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /// Request headers scripts may send besides the ones browsers always allow.
+    pub fn allow_headers(mut self, headers: &[&str]) -> Cors {
+        self.headers
+            .extend(headers.iter().map(|header| header.to_ascii_lowercase()));
+        self
+    }
+
+    /// Response headers scripts may read besides the basic ones like `Content-Type`.
+    pub fn expose_headers(mut self, headers: &[&str]) -> Cors {
+        self.expose_headers
+            .extend(headers.iter().map(|header| header.to_string()));
+        self
+    }
+
+    /// Whether requests may carry cookies. Echoing each origin back would let every
+    /// site read responses made with the user's cookies, so this can't be combined
+    /// with '*'.
+    ///
+    /// Panics if credentials are allowed while '*' is among the origins.
+    pub fn allow_credentials(mut self, credentials: bool) -> Cors {
+        if credentials && self.origins.contains(&OriginPattern::Any) {
+            panic!("CORS can't allow every origin ('*') with credentials");
+        }
+        self.credentials = credentials;
+        self
+    }
+
+    /// How long browsers may cache a preflight answer.
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed.matches(origin))
+    }
+
+    // '*' is never set together with credentials, see `allow_credentials`.
+    fn allow_origin_value<'a>(&self, origin: &'a str) -> &'a str {
+        match self.origins.contains(&OriginPattern::Any) {
+            true => "*",
+            false => origin,
+        }
+    }
+
+    fn preflight(&self, request: &Request, origin: &str, method: &str) -> Response {
+        let requested_headers: Vec<String> = request
+            .header("Access-Control-Request-Headers")
+            .unwrap_or_default()
+            .split(',')
+            .map(|header| header.trim().to_ascii_lowercase())
+            .filter(|header| !header.is_empty())
+            .collect();
+        let allowed = self.allows_origin(origin)
+            && self
+                .methods
+                .iter()
+                .any(|allowed| allowed.as_str() == method)
+            && requested_headers
+                .iter()
+                .all(|header| self.headers.contains(header));
+        if !allowed {
+            eprintln!(
+                "CORS preflight refused for {} {} from {}",
+                method,
+                request.path(),
+                origin
+            );
+            return Response::new(StatusCode::Forbidden);
+        }
+
+        let methods: Vec<&str> = self.methods.iter().map(Method::as_str).collect();
+        let mut response = Response::builder(StatusCode::NoContent)
+            .header(
+                "Access-Control-Allow-Origin",
+                self.allow_origin_value(origin),
+            )
+            .header("Access-Control-Allow-Methods", methods.join(", "));
+        if !requested_headers.is_empty() {
+            response =
+                response.header("Access-Control-Allow-Headers", requested_headers.join(", "));
+        }
+        if self.credentials {
+            response = response.header("Access-Control-Allow-Credentials", "true");
+        }
+        if let Some(max_age) = self.max_age {
+            response = response.header("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        response.empty()
+    }
+}
+
+impl Middleware for Cors {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let Some(origin) = request.header("Origin").map(str::to_string) else {
+                let mut response = next.run(request).await;
+                add_vary(&mut response, "Origin");
+                return response;
+            };
+
+            let preflight_method = request
+                .header("Access-Control-Request-Method")
+                .filter(|_| request.method == Method::Options)
+                .map(str::to_string);
+            let mut response = match preflight_method {
+                Some(method) => {
+                    let mut response = self.preflight(&request, &origin, &method);
+                    add_vary(&mut response, "Access-Control-Request-Method");
+                    add_vary(&mut response, "Access-Control-Request-Headers");
+                    response
+                }
+                None => {
+                    let mut response = next.run(request).await;
+                    if self.allows_origin(&origin) {
+                        let headers = &mut response.headers;
+                        headers.insert(
+                            "Access-Control-Allow-Origin",
+                            self.allow_origin_value(&origin),
+                        );
+                        if self.credentials {
+                            headers.insert("Access-Control-Allow-Credentials", "true");
+                        }
+                        if !self.expose_headers.is_empty() {
+                            headers.insert(
+                                "Access-Control-Expose-Headers",
+                                self.expose_headers.join(", "),
+                            );
+                        }
+                    }
+                    response
+                }
+            };
+            add_vary(&mut response, "Origin");
+            response
+        })
+    }
+}
+
+/// An allowed origin, e.g. 'https://app.example.com'. A '*' stands either for one
+/// label of the host, as in 'https://*.example.com', or for the port, as in
+/// 'http://localhost:*'. It never spans a '.' or ':', so 'https://*.example.com'
+/// doesn't match 'https://a.b.example.com', let alone 'https://example.com.evil.net'.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OriginPattern {
+    /// '*' alone, every origin.
+    Any,
+    Origin {
+        scheme: String,
+        host: String,
+        port: Option<String>,
+    },
+}
+
+impl OriginPattern {
+    pub fn parse(pattern: &str) -> Result<OriginPattern, String> {
+        let pattern = pattern.trim().trim_end_matches('/').to_ascii_lowercase();
+        if pattern == "*" {
+            return Ok(OriginPattern::Any);
+        }
+        let invalid = || format!("invalid origin '{}'", pattern);
+        let (scheme, host, port) = split_origin(&pattern).ok_or_else(invalid)?;
+        let host_ok = match host.starts_with('[') {
+            true => !host.contains('*'),
+            false => host
+                .split('.')
+                .all(|label| label == "*" || !label.contains('*')),
+        };
+        if scheme.contains('*') || !host_ok {
+            return Err(invalid());
+        }
+        Ok(OriginPattern::Origin {
+            scheme: scheme.to_string(),
+            host: host.to_string(),
+            port: port.map(str::to_string),
+        })
+    }
+
+    pub fn matches(&self, origin: &str) -> bool {
+        let origin = origin.trim().to_ascii_lowercase();
+        // A '*' sent by a client is no wildcard. An opaque origin, 'null' from a
+        // sandboxed frame or a file, doesn't split and is never trusted either.
+        if origin.contains('*') {
+            return false;
+        }
+        let Some((scheme, host, port)) = split_origin(&origin) else {
+            return false;
+        };
+        match self {
+            OriginPattern::Any => true,
+            OriginPattern::Origin {
+                scheme: allowed_scheme,
+                host: allowed_host,
+                port: allowed_port,
+            } => {
+                let labels: Vec<&str> = host.split('.').collect();
+                let allowed_labels: Vec<&str> = allowed_host.split('.').collect();
+                scheme == allowed_scheme
+                    && labels.len() == allowed_labels.len()
+                    && allowed_labels
+                        .iter()
+                        .zip(&labels)
+                        .all(|(allowed, label)| *allowed == "*" || allowed == label)
+                    && match allowed_port.as_deref() {
+                        Some("*") => true,
+                        allowed_port => allowed_port == port,
+                    }
+            }
+        }
+    }
+}
+
+// Splits 'scheme://host[:port]' into its parts, or None for anything else, including
+// 'null', paths, user info and empty labels. '*' passes, patterns check it themselves.
+fn split_origin(origin: &str) -> Option<(&str, &str, Option<&str>)> {
+    let (scheme, authority) = origin.split_once("://")?;
+    let scheme_ok = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.' | '*'));
+    if !scheme_ok {
+        return None;
+    }
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (address, rest) = rest.split_once(']')?;
+            if address.is_empty()
+                || !address
+                    .chars()
+                    .all(|c| c.is_ascii_hexdigit() || matches!(c, ':' | '.'))
+            {
+                return None;
+            }
+            let host = &authority[..address.len() + 2];
+            match rest {
+                "" => (host, None),
+                _ => (host, Some(rest.strip_prefix(':')?)),
+            }
+        }
+        None => {
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            };
+            let labels_ok = host.split('.').all(|label| {
+                !label.is_empty()
+                    && label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '*'))
+            });
+            if !labels_ok {
+                return None;
+            }
+            (host, port)
+        }
+    };
+    if let Some(port) = port {
+        if port != "*" && (port.is_empty() || !port.chars().all(|c| c.is_ascii_digit())) {
+            return None;
+        }
+    }
+    Some((scheme, host, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::Handler;
+    use crate::middleware::HandlerExt;
+    use crate::request::RequestParser;
+    use crate::router::Router;
+
+    fn request(method: &str, headers: &str) -> Request {
+        let raw = format!(
+            "{} /api HTTP/1.1\r\nHost: api.test\r\n{}\r\n",
+            method, headers
+        );
+        RequestParser::new()
+            .parse(raw.as_bytes())
+            .unwrap()
+            .unwrap()
+            .0
+    }
+
+    fn app(cors: Cors) -> impl Handler {
+        Router::new()
+            .get("/api", |_| async { Response::new(StatusCode::Ok) })
+            .post("/api", |_| async { Response::new(StatusCode::Ok) })
+            .layer(cors)
+    }
+
+    #[tokio::test]
+    async fn answers_preflight_requests() {
+        let app = app(Cors::new()
+            .allow_origin("http://localhost:*")
+            .allow_origin("https://app.test")
+            .allow_headers(&["Content-Type", "X-CSRF-Token"])
+            .allow_credentials(true)
+            .max_age(Duration::from_secs(600)));
+
+        let response = app
+            .call(request(
+                "OPTIONS",
+                "Origin: http://localhost:3000\r\nAccess-Control-Request-Method: POST\r\nAccess-Control-Request-Headers: content-type, x-csrf-token\r\n",
+            ))
+            .await;
+        assert_eq!(response.status, StatusCode::NoContent);
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            Some("http://localhost:3000")
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Methods"),
+            Some("GET, HEAD, POST")
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Headers"),
+            Some("content-type, x-csrf-token")
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+        assert!(response.headers.has_token("Vary", "Origin"));
+
+        for headers in [
+            "Origin: https://evil.test\r\nAccess-Control-Request-Method: POST\r\n",
+            "Origin: null\r\nAccess-Control-Request-Method: POST\r\n",
+            "Origin: https://app.test\r\nAccess-Control-Request-Method: DELETE\r\n",
+            "Origin: https://app.test\r\nAccess-Control-Request-Method: POST\r\nAccess-Control-Request-Headers: X-Admin\r\n",
+        ] {
+            let response = app.call(request("OPTIONS", headers)).await;
+            assert_eq!(response.status, StatusCode::Forbidden, "{}", headers);
+            assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn allows_only_listed_origins() {
+        let app = app(Cors::new()
+            .allow_origin("https://*.app.test")
+            .expose_headers(&["ETag"]));
+
+        let response = app
+            .call(request("GET", "Origin: https://eu.app.test\r\n"))
+            .await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            Some("https://eu.app.test")
+        );
+        assert_eq!(response.header("Access-Control-Allow-Credentials"), None);
+        assert_eq!(
+            response.header("Access-Control-Expose-Headers"),
+            Some("ETag")
+        );
+        assert_eq!(response.header("Vary"), Some("Origin"));
+
+        let response = app
+            .call(request("GET", "Origin: https://app.test.evil\r\n"))
+            .await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.header("Vary"), Some("Origin"));
+
+        let any = self::app(Cors::new().allow_origin("*"));
+        let response = any.call(request("GET", "Origin: https://a.test\r\n")).await;
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    }
+
+    #[test]
+    fn refuses_any_origin_with_credentials() {
+        let any_then_credentials =
+            std::panic::catch_unwind(|| Cors::new().allow_origin("*").allow_credentials(true));
+        assert!(any_then_credentials.is_err());
+        let credentials_then_any =
+            std::panic::catch_unwind(|| Cors::new().allow_credentials(true).allow_origin("*"));
+        assert!(credentials_then_any.is_err());
+        // Without credentials the origin is never echoed, only '*' is sent.
+        assert_eq!(
+            Cors::new()
+                .allow_origin("*")
+                .allow_origin_value("https://a.test"),
+            "*"
+        );
+    }
+
+    #[test]
+    fn wildcards_match_one_label_or_the_port() {
+        let pattern = |pattern| OriginPattern::parse(pattern).unwrap();
+
+        let subdomain = pattern("https://*.example.com");
+        assert!(subdomain.matches("https://app.example.com"));
+        assert!(subdomain.matches("HTTPS://App.Example.com"));
+        for origin in [
+            "https://evil.com.example.com.attacker.net",
+            "https://a.b.example.com",
+            "https://example.com",
+            "https://.example.com",
+            "http://app.example.com",
+            "https://app.example.com:8443",
+            "https://app.example.com/path",
+            "https://user@app.example.com",
+            "https://*.example.com",
+            "null",
+        ] {
+            assert!(!subdomain.matches(origin), "{}", origin);
+        }
+
+        let tld = pattern("https://app.example.*");
+        assert!(tld.matches("https://app.example.org"));
+        assert!(!tld.matches("https://app.example.com.evil.net"));
+        assert!(!tld.matches("https://app.example.com:1"));
+
+        let port = pattern("http://localhost:*");
+        assert!(port.matches("http://localhost:3000"));
+        assert!(port.matches("http://localhost"));
+        assert!(!port.matches("http://localhost.evil.net:3000"));
+        assert!(!port.matches("http://localhost:3000.evil.net"));
+
+        let ipv6 = pattern("http://[::1]:8080");
+        assert!(ipv6.matches("http://[::1]:8080"));
+        assert!(!ipv6.matches("http://[::1]:8081"));
+
+        assert_eq!(pattern(" * "), OriginPattern::Any);
+        for invalid in [
+            "app.example.com",
+            "https://",
+            "https://app*.example.com",
+            "https://a..example.com",
+            "https://example.com:80a",
+            "https://example.com/path",
+            "*://example.com",
+            "null",
+        ] {
+            assert!(OriginPattern::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
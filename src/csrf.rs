@@ -0,0 +1,571 @@
+#![forbid(unsafe_code)]
+
+use crate::cookie::{Cookie, CookieKeys, SameSite};
+use crate::cors::OriginPattern;
+use crate::error::SessionError;
+use crate::middleware::{Middleware, Next};
+use crate::multipart;
+use crate::request::{Method, Request};
+use crate::response::Response;
+use crate::session::{Session, SessionManager};
+use crate::status::StatusCode;
+use futures::future::BoxFuture;
+use subtle::ConstantTimeEq;
+
+/// The request header carrying the token, for `fetch` and other scripted requests.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+/// The form field carrying the token, for plain HTML forms, urlencoded or multipart.
+pub const CSRF_FIELD: &str = "csrf_token";
+// Where the synchronizer token is kept in the session's data.
+const SESSION_KEY: &str = "csrf_token";
+
+/// Rejects cross-site requests that could change state with a 403 (cross-site request
+/// forgery). GET, HEAD, OPTIONS and TRACE pass, everything else needs both:
+///
+/// - An `Origin` matching the request's `Host` or a trusted origin. Without `Origin`,
+///   `Sec-Fetch-Site` must not say the request came from another site.
+/// - A token in the `X-CSRF-Token` header or the 'csrf_token' form field. It either
+///   matches the session's synchronizer token (see `session_token`), or is a
+///   double-submit token equal to the signed CSRF cookie.
+///
+/// The CSRF cookie is handed out with responses to safe requests, except publicly
+/// cacheable ones, which are then marked `Cache-Control: private`. It is readable by
+/// scripts so they can copy it into the header. It is signed with the `CookieKeys` in
+/// the server state together with the id in the client's session cookie, so it only
+/// passes with that session: a cookie someone fetched for themselves and planted
+/// doesn't. A response that changes the session cookie, e.g. on login, comes with a new
+/// CSRF cookie. Before there is a session there is nothing to bind to, and only the
+/// '__Host-' prefix (see `secure`) keeps sibling subdomains from planting a cookie.
+pub struct Csrf {
+    secure: bool,
+    trusted_origins: Vec<OriginPattern>,
+    exempt: Vec<String>,
+    // Used when the server state has no keys.
+    keys: CookieKeys,
+}
+
+impl Default for Csrf {
+    fn default() -> Csrf {
+        Csrf::new()
+    }
+}
+
+impl Csrf {
+    pub fn new() -> Csrf {
+        Csrf {
+            secure: true,
+            trusted_origins: Vec::new(),
+            exempt: Vec::new(),
+            keys: CookieKeys::generate(),
+        }
+    }
+
+    /// Whether the cookie is for HTTPS only, as '__Host-csrf', or plain HTTP as 'csrf'.
+    pub fn secure(mut self, secure: bool) -> Csrf {
+        self.secure = secure;
+        self
+    }
+
+    /// Another origin allowed to send requests, e.g. 'https://app.example.com', or a
+    /// pattern like those of `Cors::allow_origin`.
+    ///
+    /// Panics if it isn't an origin or pattern, or if it is '*', which would turn the
+    /// Origin check off for every site.
+    pub fn trusted_origin(mut self, origin: &str) -> Csrf {
+        match OriginPattern::parse(origin) {
+            Ok(OriginPattern::Any) => panic!("CSRF protection can't trust every origin ('*')"),
+            Ok(pattern) => self.trusted_origins.push(pattern),
+            Err(err) => panic!("{}", err),
+        }
+        self
+    }
+
+    /// A path that isn't checked, e.g. for webhooks authenticated some other way.
+    pub fn exempt(mut self, path: &str) -> Csrf {
+        self.exempt.push(path.to_string());
+        self
+    }
+
+    pub fn cookie_name(&self) -> &'static str {
+        match self.secure {
+            true => "__Host-csrf",
+            false => "csrf",
+        }
+    }
+
+    fn new_cookie(&self, keys: &CookieKeys, session: &str) -> Cookie {
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        let cookie = Cookie::new(self.cookie_name(), &token)
+            .expect("hex tokens are valid cookie values")
+            .path("/")
+            .secure(self.secure)
+            .same_site(SameSite::Strict);
+        keys.sign_bound(cookie, session)
+    }
+
+    // When the response sets or removes the session cookie, e.g. on login or logout, the
+    // CSRF cookie gets bound to the new session. Returns whether it did.
+    fn rebind(
+        &self,
+        sessions: Option<&SessionManager>,
+        response: &mut Response,
+        keys: &CookieKeys,
+    ) -> bool {
+        let Some(sessions) = sessions else {
+            return false;
+        };
+        let session = response
+            .headers
+            .get_all("Set-Cookie")
+            .filter_map(|cookie| cookie.split(';').next()?.split_once('='))
+            .find(|(name, _)| name.trim() == sessions.config().cookie_name)
+            .map(|(_, value)| value.trim().to_string());
+        let Some(session) = session else {
+            return false;
+        };
+        response
+            .headers
+            .append("Set-Cookie", self.new_cookie(keys, &session).to_string());
+        keep_private(response);
+        true
+    }
+
+    fn check_origin(&self, request: &Request) -> Result<(), &'static str> {
+        if let Some(origin) = request.header("Origin") {
+            let origin = origin.trim().to_ascii_lowercase();
+            let host = request
+                .header("Host")
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            let same_origin = origin
+                .split_once("://")
+                .is_some_and(|(_, authority)| !host.is_empty() && authority == host);
+            let trusted = self
+                .trusted_origins
+                .iter()
+                .any(|trusted| trusted.matches(&origin));
+            return match same_origin || trusted {
+                true => Ok(()),
+                false => Err("untrusted Origin"),
+            };
+        }
+        match request.header("Sec-Fetch-Site") {
+            Some("cross-site") | Some("same-site") => Err("cross-site Sec-Fetch-Site"),
+            _ => Ok(()),
+        }
+    }
+
+    async fn check_token(&self, request: &Request, keys: &CookieKeys) -> Result<(), &'static str> {
+        let submitted = match request.header(CSRF_HEADER) {
+            Some(token) => token.trim().to_string(),
+            None => match request.media_type().as_deref() {
+                Some("multipart/form-data") => multipart::text_field(request, CSRF_FIELD).await,
+                _ => request
+                    .form()
+                    .ok()
+                    .and_then(|form| form.get(CSRF_FIELD).map(str::to_string)),
+            }
+            .ok_or("no token")?,
+        };
+
+        if let Some(sessions) = request.state::<SessionManager>() {
+            if let Ok(Some(session)) = sessions.load(request).await {
+                if let Some(expected) = session.data.get(SESSION_KEY) {
+                    if constant_time_eq(&submitted, expected) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        let jar = request.cookies();
+        let signed = jar.get(self.cookie_name()).ok_or("no CSRF cookie")?;
+        if jar
+            .get_signed_bound(self.cookie_name(), keys, &session_binding(request))
+            .is_none()
+        {
+            return Err("forged CSRF cookie or one of another session");
+        }
+        match constant_time_eq(&submitted, signed) {
+            true => Ok(()),
+            false => Err("token mismatch"),
+        }
+    }
+}
+
+impl Middleware for Csrf {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let keys = request
+                .state::<CookieKeys>()
+                .cloned()
+                .unwrap_or_else(|| self.keys.clone());
+            if matches!(
+                request.method,
+                Method::Get | Method::Head | Method::Options | Method::Trace
+            ) {
+                let session = session_binding(&request);
+                let has_cookie = request
+                    .cookies()
+                    .get_signed_bound(self.cookie_name(), &keys, &session)
+                    .is_some();
+                let sessions = request.state::<SessionManager>().cloned();
+                let mut response = next.run(request).await;
+                if !self.rebind(sessions.as_ref(), &mut response, &keys)
+                    && !has_cookie
+                    && !publicly_cacheable(&response)
+                {
+                    response
+                        .headers
+                        .append("Set-Cookie", self.new_cookie(&keys, &session).to_string());
+                    keep_private(&mut response);
+                }
+                return response;
+            }
+            if self.exempt.iter().any(|path| path == request.path()) {
+                return next.run(request).await;
+            }
+
+            let checked = match self.check_origin(&request) {
+                Ok(()) => self.check_token(&request, &keys).await,
+                Err(reason) => Err(reason),
+            };
+            if let Err(reason) = checked {
+                eprintln!(
+                    "CSRF check failed for {} {}: {}",
+                    request.method.as_str(),
+                    request.path(),
+                    reason
+                );
+                return Response::new(StatusCode::Forbidden);
+            }
+            let sessions = request.state::<SessionManager>().cloned();
+            let mut response = next.run(request).await;
+            self.rebind(sessions.as_ref(), &mut response, &keys);
+            response
+        })
+    }
+}
+
+// A shared cache would hand such a response, cookie included, to everyone.
+fn publicly_cacheable(response: &Response) -> bool {
+    response
+        .headers
+        .get_all("Cache-Control")
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .any(|directive| directive == "public" || directive.starts_with("s-maxage"))
+}
+
+// Keeps a response carrying a fresh cookie out of shared caches.
+fn keep_private(response: &mut Response) {
+    let headers = &mut response.headers;
+    if headers.has_token("Cache-Control", "private")
+        || headers.has_token("Cache-Control", "no-store")
+    {
+        return;
+    }
+    let value = match headers.get("Cache-Control") {
+        Some(value) => format!("private, {}", value),
+        None => "private".to_string(),
+    };
+    headers.insert("Cache-Control", value);
+}
+
+// What the CSRF cookie is bound to: the id in the request's session cookie, empty when
+// there is none.
+fn session_binding(request: &Request) -> String {
+    request
+        .state::<SessionManager>()
+        .and_then(|sessions| {
+            request
+                .cookies()
+                .get(&sessions.config().cookie_name)
+                .map(str::to_string)
+        })
+        .unwrap_or_default()
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+/// The synchronizer token of a session, created on first use. Put it in forms as
+/// 'csrf_token', or in the `X-CSRF-Token` header of scripted requests.
+pub async fn session_token(
+    sessions: &SessionManager,
+    session: &mut Session,
+) -> Result<String, SessionError> {
+    if let Some(token) = session.data.get(SESSION_KEY) {
+        return Ok(token.clone());
+    }
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    session.data.insert(SESSION_KEY.to_string(), token.clone());
+    sessions.save(session).await?;
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::Handler;
+    use crate::middleware::HandlerExt;
+    use crate::request::RequestParser;
+    use crate::router::Router;
+    use crate::session::{MemoryStore, SessionConfig};
+    use crate::state::AppState;
+
+    fn state() -> AppState {
+        let mut state = AppState::new();
+        state.insert(CookieKeys::generate());
+        state.insert(SessionManager::new(
+            MemoryStore::new(),
+            SessionConfig::default(),
+        ));
+        state
+    }
+
+    fn request(state: &AppState, method: &str, path: &str, headers: &str) -> Request {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nHost: app.test\r\nContent-Length: 0\r\n{}\r\n",
+            method, path, headers
+        );
+        let mut request = RequestParser::new()
+            .parse(raw.as_bytes())
+            .unwrap()
+            .unwrap()
+            .0;
+        request.state = state.clone();
+        request
+    }
+
+    fn app() -> impl Handler {
+        Router::new()
+            .get("/", |_| async { Response::new(StatusCode::Ok) })
+            .post("/transfer", |_| async { Response::new(StatusCode::Ok) })
+            .post("/hook", |_| async { Response::new(StatusCode::Ok) })
+            .layer(
+                Csrf::new()
+                    .trusted_origin("https://front.test/")
+                    .exempt("/hook"),
+            )
+    }
+
+    #[tokio::test]
+    async fn requires_double_submit_tokens() {
+        let (state, app) = (state(), app());
+        let response = app.call(request(&state, "GET", "/", "")).await;
+        let set_cookie = response.header("Set-Cookie").unwrap().to_string();
+        assert!(set_cookie.ends_with("; Path=/; Secure; SameSite=Strict"));
+        let cookie = set_cookie.split(';').next().unwrap();
+        let token = cookie.strip_prefix("__Host-csrf=").unwrap();
+        let with_cookie = format!("Cookie: {}\r\n", cookie);
+
+        let check = |headers: String| {
+            let request = request(&state, "POST", "/transfer", &headers);
+            async { app.call(request).await.status }
+        };
+        assert_eq!(check(with_cookie.clone()).await, StatusCode::Forbidden);
+        assert_eq!(
+            check(format!("{}X-CSRF-Token: {}\r\n", with_cookie, token)).await,
+            StatusCode::Ok
+        );
+        assert_eq!(
+            check(format!("X-CSRF-Token: {}\r\n", token)).await,
+            StatusCode::Forbidden
+        );
+        // A cookie set by someone else doesn't carry our signature.
+        let forged = "Cookie: __Host-csrf=abc.def\r\nX-CSRF-Token: abc.def\r\n";
+        assert_eq!(check(forged.to_string()).await, StatusCode::Forbidden);
+
+        let valid = format!("{}X-CSRF-Token: {}\r\n", with_cookie, token);
+        for (origin, status) in [
+            ("Origin: https://app.test\r\n", StatusCode::Ok),
+            ("Origin: https://front.test\r\n", StatusCode::Ok),
+            ("Origin: https://evil.test\r\n", StatusCode::Forbidden),
+            ("Origin: null\r\n", StatusCode::Forbidden),
+            ("Sec-Fetch-Site: cross-site\r\n", StatusCode::Forbidden),
+            ("Sec-Fetch-Site: same-origin\r\n", StatusCode::Ok),
+        ] {
+            assert_eq!(
+                check(format!("{}{}", valid, origin)).await,
+                status,
+                "{}",
+                origin
+            );
+        }
+        assert_eq!(check(String::new()).await, StatusCode::Forbidden);
+        let hook = request(&state, "POST", "/hook", "");
+        assert_eq!(app.call(hook).await.status, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn keeps_cookies_out_of_shared_caches() {
+        let state = state();
+        let app = Router::new()
+            .get("/", |_| async { Response::new(StatusCode::Ok) })
+            .get("/logo.png", |_| async {
+                Response::builder(StatusCode::Ok)
+                    .header("Cache-Control", "public, max-age=86400")
+                    .empty()
+            })
+            .get("/news", |_| async {
+                Response::builder(StatusCode::Ok)
+                    .header("Cache-Control", "max-age=60")
+                    .empty()
+            })
+            .layer(Csrf::new());
+
+        let response = app.call(request(&state, "GET", "/logo.png", "")).await;
+        assert_eq!(response.header("Set-Cookie"), None);
+        assert_eq!(
+            response.header("Cache-Control"),
+            Some("public, max-age=86400")
+        );
+
+        let response = app.call(request(&state, "GET", "/", "")).await;
+        assert!(response.header("Set-Cookie").is_some());
+        assert_eq!(response.header("Cache-Control"), Some("private"));
+        let response = app.call(request(&state, "GET", "/news", "")).await;
+        assert!(response.header("Set-Cookie").is_some());
+        assert_eq!(
+            response.header("Cache-Control"),
+            Some("private, max-age=60")
+        );
+    }
+
+    #[tokio::test]
+    async fn accepts_session_tokens() {
+        let (state, app) = (state(), app());
+        let sessions = state.get::<SessionManager>().unwrap();
+        let (mut session, cookie) = sessions
+            .login(&request(&state, "GET", "/", ""), "mock1")
+            .await
+            .unwrap();
+        let token = session_token(sessions, &mut session).await.unwrap();
+        assert_eq!(session_token(sessions, &mut session).await.unwrap(), token);
+
+        let session_cookie = format!("Cookie: {}={}\r\n", cookie.name(), cookie.value());
+        let form = request(
+            &state,
+            "POST",
+            "/transfer",
+            &format!(
+                "{}Content-Type: application/x-www-form-urlencoded\r\n",
+                session_cookie
+            ),
+        );
+        let mut form = form;
+        form.body = format!("amount=5&csrf_token={}", token).into_bytes();
+        assert_eq!(app.call(form).await.status, StatusCode::Ok);
+
+        let mut upload = request(
+            &state,
+            "POST",
+            "/transfer",
+            &format!(
+                "{}Content-Type: multipart/form-data; boundary=XyZ\r\n",
+                session_cookie
+            ),
+        );
+        upload.body = format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"csrf_token\"\r\n\r\n{}\r\n--XyZ\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\n{}\r\n--XyZ--\r\n",
+            "0".repeat(64),
+            token
+        )
+        .into_bytes();
+        assert_eq!(app.call(upload).await.status, StatusCode::Ok);
+
+        let wrong = format!("{}X-CSRF-Token: {}\r\n", session_cookie, "0".repeat(64));
+        let response = app.call(request(&state, "POST", "/transfer", &wrong)).await;
+        assert_eq!(response.status, StatusCode::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn binds_cookies_to_the_session() {
+        let state = state();
+        let app = Router::new()
+            .get("/", |_| async { Response::new(StatusCode::Ok) })
+            .post("/transfer", |_| async { Response::new(StatusCode::Ok) })
+            .post("/login", |_| async {
+                Response::builder(StatusCode::Ok)
+                    .header("Set-Cookie", "__Host-session=second; Path=/")
+                    .empty()
+            })
+            .layer(Csrf::new());
+        let csrf_cookie = |response: &Response| {
+            let set_cookie = response
+                .headers
+                .get_all("Set-Cookie")
+                .find(|cookie| cookie.starts_with("__Host-csrf="))
+                .unwrap()
+                .to_string();
+            set_cookie.split(';').next().unwrap().to_string()
+        };
+
+        let response = app
+            .call(request(
+                &state,
+                "GET",
+                "/",
+                "Cookie: __Host-session=first\r\n",
+            ))
+            .await;
+        let cookie = csrf_cookie(&response);
+        let token = cookie.strip_prefix("__Host-csrf=").unwrap().to_string();
+        let post = |path: &str, session: &str, cookie: &str| {
+            let headers = format!(
+                "Cookie: __Host-session={}; {}\r\nX-CSRF-Token: {}\r\n",
+                session,
+                cookie,
+                cookie.split_once('=').unwrap().1
+            );
+            app.call(request(&state, "POST", path, &headers))
+        };
+        assert_eq!(
+            post("/transfer", "first", &cookie).await.status,
+            StatusCode::Ok
+        );
+        // Planted into another client's browser, it is worthless.
+        assert_eq!(
+            post("/transfer", "other", &cookie).await.status,
+            StatusCode::Forbidden
+        );
+        let without_session = format!("Cookie: {}\r\nX-CSRF-Token: {}\r\n", cookie, token);
+        let response = app
+            .call(request(&state, "POST", "/transfer", &without_session))
+            .await;
+        assert_eq!(response.status, StatusCode::Forbidden);
+
+        // A new session brings a new cookie along.
+        let response = post("/login", "first", &cookie).await;
+        let rebound = csrf_cookie(&response);
+        assert_eq!(response.header("Cache-Control"), Some("private"));
+        assert_eq!(
+            post("/transfer", "second", &rebound).await.status,
+            StatusCode::Ok
+        );
+        assert_eq!(
+            post("/transfer", "second", &cookie).await.status,
+            StatusCode::Forbidden
+        );
+    }
+
+    #[test]
+    fn trusts_only_exact_origin_patterns() {
+        assert!(std::panic::catch_unwind(|| Csrf::new().trusted_origin("*")).is_err());
+
+        let csrf = Csrf::new().trusted_origin("https://*.front.test");
+        let state = state();
+        for (origin, trusted) in [
+            ("https://eu.front.test", true),
+            ("https://front.test.evil.net", false),
+            ("https://evil.net.front.test.attacker.net", false),
+            ("https://a.b.front.test", false),
+            ("http://eu.front.test", false),
+        ] {
+            let request = request(&state, "POST", "/", &format!("Origin: {}\r\n", origin));
+            assert_eq!(csrf.check_origin(&request).is_ok(), trusted, "{}", origin);
+        }
+    }
+}
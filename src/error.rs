@@ -29,6 +29,8 @@ impl Error for ConfigError {} // ConfigError is of type Error
 pub enum PsqlError {
     SqlxError(sqlxerror),
     PasswordMismatch,
+    /// Hashing or verifying a password failed, e.g. on a malformed stored hash.
+    PasswordHash(String),
 }
 
 impl From<sqlxerror> for PsqlError {
@@ -37,13 +39,161 @@ impl From<sqlxerror> for PsqlError {
     }
 }
 
+impl From<argon2::password_hash::Error> for PsqlError {
+    fn from(err: argon2::password_hash::Error) -> Self {
+        PsqlError::PasswordHash(err.to_string())
+    }
+}
+
 impl std::fmt::Display for PsqlError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             PsqlError::SqlxError(err) => write!(f, "SQLx error: {}", err),
             PsqlError::PasswordMismatch => write!(f, "Passwords don't match"),
+            PsqlError::PasswordHash(err) => write!(f, "Password hashing error: {}", err),
         }
     }
 }
 
+impl PsqlError {
+    /// The database couldn't be reached, or the pool had no connection to spare in time.
+    /// Worth a 503 rather than a 500, it usually goes away by itself.
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self, PsqlError::SqlxError(err) if is_connection_error(err))
+    }
+}
+
 impl Error for PsqlError {}
+
+fn is_connection_error(err: &sqlxerror) -> bool {
+    matches!(
+        err,
+        sqlxerror::PoolTimedOut | sqlxerror::PoolClosed | sqlxerror::Io(_) | sqlxerror::Tls(_)
+    )
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CookieError {
+    InvalidName(String),
+    /// The value for the named cookie has characters cookies can't carry.
+    InvalidValue(String),
+    InvalidKey(String),
+}
+
+impl fmt::Display for CookieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CookieError::InvalidName(name) => write!(f, "Invalid cookie name: {:?}", name),
+            CookieError::InvalidValue(name) => write!(f, "Invalid value for cookie '{}'", name),
+            CookieError::InvalidKey(reason) => write!(f, "Invalid cookie key: {}", reason),
+        }
+    }
+}
+
+impl Error for CookieError {}
+
+#[derive(Debug)]
+pub enum SessionError {
+    /// The session store's database failed.
+    Store(sqlxerror),
+    /// The configured cookie name can't be used.
+    Cookie(CookieError),
+    /// A stored session can't be read back.
+    Corrupt(String),
+}
+
+impl SessionError {
+    /// Like `PsqlError::is_unavailable`, worth a 503.
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            SessionError::Store(err) => is_connection_error(err),
+            SessionError::Cookie(_) | SessionError::Corrupt(_) => false,
+        }
+    }
+}
+
+impl From<sqlxerror> for SessionError {
+    fn from(err: sqlxerror) -> Self {
+        SessionError::Store(err)
+    }
+}
+
+impl From<CookieError> for SessionError {
+    fn from(err: CookieError) -> Self {
+        SessionError::Cookie(err)
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::Store(err) => write!(f, "Session store error: {}", err),
+            SessionError::Cookie(err) => write!(f, "Session cookie error: {}", err),
+            SessionError::Corrupt(reason) => write!(f, "Corrupt session: {}", reason),
+        }
+    }
+}
+
+impl Error for SessionError {}
+
+#[derive(Debug, PartialEq)]
+pub enum HttpError {
+    BadRequest(String),
+    VersionNotSupported(String),
+    NotImplemented(String),
+    HeadersTooLarge(usize),
+    PayloadTooLarge(usize),
+    UnsupportedMediaType(String),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpError::BadRequest(reason) => write!(f, "Bad request: {}", reason),
+            HttpError::VersionNotSupported(version) => {
+                write!(f, "HTTP version not supported: {}", version)
+            }
+            HttpError::NotImplemented(feature) => write!(f, "Not implemented: {}", feature),
+            HttpError::HeadersTooLarge(limit) => {
+                write!(f, "Request head exceeds the limit of {} bytes", limit)
+            }
+            HttpError::PayloadTooLarge(limit) => {
+                write!(f, "Request body exceeds the limit of {} bytes", limit)
+            }
+            HttpError::UnsupportedMediaType(media_type) => {
+                write!(f, "Unsupported media type: {}", media_type)
+            }
+        }
+    }
+}
+
+impl Error for HttpError {}
+
+#[derive(Debug)]
+pub enum FormError {
+    Http(HttpError),
+    Io(std::io::Error),
+}
+
+impl From<HttpError> for FormError {
+    fn from(err: HttpError) -> Self {
+        FormError::Http(err)
+    }
+}
+
+impl From<std::io::Error> for FormError {
+    fn from(err: std::io::Error) -> Self {
+        FormError::Io(err)
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormError::Http(err) => write!(f, "{}", err),
+            FormError::Io(err) => write!(f, "Failed to store uploaded file: {}", err),
+        }
+    }
+}
+
+impl Error for FormError {}
@@ -0,0 +1,30 @@
+#![forbid(unsafe_code)]
+
+use crate::request::Request;
+use crate::response::Response;
+use futures::future::BoxFuture;
+use std::future::Future;
+
+/// Anything that turns a request into a response: a `Router`, or a plain async function
+/// or closure taking a `Request`. Pass one to `Server::with_handler` to serve your own routes.
+///
+/// ```ignore
+/// async fn hello(_request: Request) -> Response {
+///     Response::html(StatusCode::Ok, "<h1>Hello</h1>")
+/// }
+///
+/// let server = Server::init(opts)?.with_handler(Router::new().get("/hello", hello));
+/// ```
+pub trait Handler: Send + Sync + 'static {
+    fn call(&self, request: Request) -> BoxFuture<'_, Response>;
+}
+
+impl<F, Fut> Handler for F
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    fn call(&self, request: Request) -> BoxFuture<'_, Response> {
+        Box::pin(self(request))
+    }
+}
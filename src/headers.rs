@@ -0,0 +1,140 @@
+#![forbid(unsafe_code)]
+
+/// An ordered, case-insensitive collection of HTTP header fields.
+/// Field order is kept as received and repeated fields are stored as separate entries,
+/// so nothing is lost when a request or response is serialized back to the wire.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the value of the first field called `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the values of every field called `name`, in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+
+    /// Sets `name` to `value`, replacing any fields with the same name.
+    pub fn insert(&mut self, name: &str, value: impl Into<String>) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Adds a field without touching existing fields with the same name.
+    pub fn append(&mut self, name: &str, value: impl Into<String>) {
+        self.entries
+            .push((field_text(name.to_string()), field_text(value.into())));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Checks whether a comma separated field such as `Connection` lists `token`.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+}
+
+// CR, LF and NUL never belong in a field. Stripping them means a value echoed from
+// user input, e.g. in a Location or Set-Cookie, can't end the field early and add
+// its own (response splitting).
+fn field_text(text: String) -> String {
+    match text.contains(['\r', '\n', '\0']) {
+        true => text.replace(['\r', '\n', '\0'], ""),
+        false => text,
+    }
+}
+
+/// Splits a field value such as `multipart/form-data; boundary="x y"` or
+/// `form-data; name="file"` into the leading value and its parameters.
+/// Parameter names are lowercased and quoted values are unescaped.
+pub fn parse_parameters(value: &str) -> (&str, Vec<(String, String)>) {
+    let (main, mut rest) = match value.find(';') {
+        Some(index) => (&value[..index], &value[index + 1..]),
+        None => (value, ""),
+    };
+    let mut params = Vec::new();
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        if rest.is_empty() {
+            break;
+        }
+        let name_end = rest.find(['=', ';']).unwrap_or(rest.len());
+        let name = rest[..name_end].trim().to_ascii_lowercase();
+        rest = &rest[name_end..];
+        let mut param_value = String::new();
+
+        if let Some(after_equals) = rest.strip_prefix('=') {
+            let after_equals = after_equals.trim_start();
+            if let Some(quoted) = after_equals.strip_prefix('"') {
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((index, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                param_value.push(escaped);
+                            }
+                        }
+                        '"' => {
+                            end = index + 1;
+                            break;
+                        }
+                        _ => param_value.push(c),
+                    }
+                }
+                rest = &quoted[end..];
+            } else {
+                let value_end = after_equals.find(';').unwrap_or(after_equals.len());
+                param_value = after_equals[..value_end].trim_end().to_string();
+                rest = &after_equals[value_end..];
+            }
+        }
+        if !name.is_empty() {
+            params.push((name, param_value));
+        }
+    }
+    (main.trim(), params)
+}
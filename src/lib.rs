@@ -1,24 +1,63 @@
 #![forbid(unsafe_code)]
 
-use std::{error::Error, fs, sync::Arc};
+use std::time::Duration;
+use std::{env, error::Error, fs, sync::Arc};
 use tokio::net::TcpListener;
 use tokio_rustls::rustls;
 use tokio_rustls::TlsAcceptor;
 
+pub mod asset_cache;
+pub mod audit;
+pub mod chunked;
 pub mod cli;
+pub mod compression;
+pub mod conditional;
+pub mod cookie;
+pub mod cors;
+pub mod csrf;
 pub mod error;
+pub mod handler;
+pub mod headers;
+pub mod middleware;
 pub mod models;
+pub mod multipart;
+pub mod password;
 pub mod psql;
+pub mod query;
+pub mod range;
+pub mod rate_limit;
+pub mod request;
+pub mod response;
 pub mod route;
+pub mod router;
+pub mod session;
+pub mod state;
+pub mod static_files;
 pub mod status;
+#[cfg(test)]
+mod test_support;
 use crate::cli::ServerConfigArguments;
-use crate::route::{handle_connection_async, TcpStreamType};
+use crate::cookie::CookieKeys;
+use crate::error::ConfigError;
+use crate::handler::Handler;
+use crate::password::PasswordConfig;
+use crate::psql::{db_psql_health_check, db_psql_pool, PoolConfig};
+use crate::rate_limit::{LoginLimiter, MemoryLockoutStore, PgLockoutStore, RateLimitConfig};
+use crate::request::Limits;
+use crate::route::{default_handler, handle_connection_async, TcpStreamType};
+use crate::session::{MemoryStore, PgStore, SessionConfig, SessionManager};
+use crate::state::AppState;
+use sqlx::postgres::PgPool;
 use std::collections::HashMap;
 
 pub struct Server {
     ip_port: String,
     pub with_tls: bool,
     pub verbose: bool,
+    pub limits: Limits,
+    pub keep_alive_timeout: Duration,
+    handler: Arc<dyn Handler>,
+    state: AppState,
 }
 
 impl Server {
@@ -31,28 +70,143 @@ impl Server {
         let ip_addr = opts_flags.get(&ServerConfigArguments::IpAddress).unwrap();
         let port = opts_flags.get(&ServerConfigArguments::Port).unwrap();
         let ip_port = format!("{}:{}", ip_addr, port);
-        let with_tls = opts_flags.get(&ServerConfigArguments::Tls).is_none();
-        let verbose = opts_flags.get(&ServerConfigArguments::Verbose).is_some();
+        let with_tls = !opts_flags.contains_key(&ServerConfigArguments::Tls);
+        let verbose = opts_flags.contains_key(&ServerConfigArguments::Verbose);
 
+        let mut limits = Limits::default();
+        if let Some(size) = opts_flags.get(&ServerConfigArguments::MaxHeaderSize) {
+            limits.max_header_size = parse_size(size, "-maxheader")?;
+            // Not even a request line would fit, every request would get a 431.
+            if limits.max_header_size == 0 {
+                return Err(
+                    ConfigError::ParseError("'-maxheader' must be at least 1".to_string()).into(),
+                );
+            }
+        }
+        if let Some(size) = opts_flags.get(&ServerConfigArguments::MaxBodySize) {
+            limits.max_body_size = parse_size(size, "-maxbody")?;
+        }
+        let mut keep_alive_timeout = Duration::from_secs(5);
+        if let Some(secs) = opts_flags.get(&ServerConfigArguments::KeepAliveTimeout) {
+            keep_alive_timeout = parse_seconds(secs, "-keepalive")?;
+        }
+
+        let cors_origins: Vec<String> = env::var("CORS_ORIGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        // The pool is created once here and shared by every connection through the state.
+        // It connects lazily, `start_async` checks that the database is actually reachable.
+        let mut state = AppState::new();
+        state.insert(PasswordConfig::from_env()?);
+        state.insert(match CookieKeys::from_env()? {
+            Some(keys) => keys,
+            None => {
+                println!("[ WARN ]     'COOKIE_KEYS' is not set, signed and encrypted cookies won't survive a restart.");
+                CookieKeys::generate()
+            }
+        });
+        let mut session_config = SessionConfig::default();
+        if !with_tls {
+            // Browsers refuse 'Secure' and '__Host-' cookies over plain HTTP.
+            session_config.cookie_name = "session".to_string();
+            session_config.secure = false;
+        }
+        let rate_limit_config = RateLimitConfig::from_env()?;
+        match PoolConfig::from_env()? {
+            Some(config) => {
+                let pool = db_psql_pool(&config)?;
+                state.insert(SessionManager::new(
+                    PgStore::new(pool.clone()),
+                    session_config,
+                ));
+                state.insert(LoginLimiter::new(
+                    PgLockoutStore::new(pool.clone()),
+                    rate_limit_config,
+                ));
+                state.insert(pool);
+            }
+            None => {
+                state.insert(SessionManager::new(MemoryStore::new(), session_config));
+                state.insert(LoginLimiter::new(
+                    MemoryLockoutStore::new(),
+                    rate_limit_config,
+                ));
+            }
+        }
+
+        let handler = default_handler(with_tls, &cors_origins)?;
         Ok(Server {
             ip_port,
             with_tls,
             verbose,
+            limits,
+            keep_alive_timeout,
+            handler: Arc::new(handler),
+            state,
         })
     }
 
+    /// Registers a value that handlers and middleware can get with `Request::state`,
+    /// one per type. Call it before starting the server.
+    pub fn with_state<T: Send + Sync + 'static>(mut self, value: T) -> Server {
+        self.state.insert(value);
+        self
+    }
+
+    /// The shared state. Holds the `PgPool` built from 'DATABASE_URL' when that is set.
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    // Startup verification of the database. A failure isn't fatal: the pages are
+    // still served and login answers with 503 until the database comes back.
+    async fn check_database(&self) {
+        let Some(pool) = self.state.get::<PgPool>() else {
+            println!("[ WARN ]     'DATABASE_URL' is not set, login is unavailable.");
+            return;
+        };
+        match db_psql_health_check(pool).await {
+            Ok(()) => println!("[  OK  ]     Connected to the database."),
+            Err(e) => println!("[ WARN ]     Database is not reachable: {}", e),
+        }
+    }
+
+    /// Replaces the built-in routes with your own handler, typically a `Router`.
+    /// Call it before starting the server.
+    pub fn with_handler(mut self, handler: impl Handler) -> Server {
+        self.handler = Arc::new(handler);
+        self
+    }
+
     /// Starts the server using async
     ///
     pub async fn start_async(&self) -> Result<(), Box<dyn Error>> {
+        self.check_database().await;
         let listener = tokio::net::TcpListener::bind(&self.ip_port).await?;
         println!("[  OK  ]     Started the server and serving requests using async, no TLS.");
 
         loop {
             let (socket, _) = listener.accept().await?;
+            let limits = self.limits;
+            let keep_alive_timeout = self.keep_alive_timeout;
+            let handler = self.handler.clone();
+            let state = self.state.clone();
 
             tokio::spawn(async move {
                 // Process each socket concurrently.
-                handle_connection_async(&mut TcpStreamType::TokioNoTls(socket)).await;
+                handle_connection_async(
+                    &mut TcpStreamType::TokioNoTls(socket),
+                    limits,
+                    keep_alive_timeout,
+                    handler,
+                    state,
+                )
+                .await;
             });
         }
     }
@@ -69,6 +223,7 @@ impl Server {
             .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
 
         let acceptor = TlsAcceptor::from(Arc::new(config));
+        self.check_database().await;
         let listener = TcpListener::bind(&self.ip_port).await?;
         println!("[  OK  ]     Started the TLS server in async mode.");
 
@@ -76,11 +231,21 @@ impl Server {
             match listener.accept().await {
                 Ok((socket, _ip_addr)) => {
                     let acceptor = acceptor.clone();
+                    let limits = self.limits;
+                    let keep_alive_timeout = self.keep_alive_timeout;
+                    let handler = self.handler.clone();
+                    let state = self.state.clone();
                     tokio::spawn(async move {
                         match acceptor.accept(socket).await {
                             Ok(tls_stream) => {
-                                handle_connection_async(&mut TcpStreamType::TokioTls(tls_stream))
-                                    .await;
+                                handle_connection_async(
+                                    &mut TcpStreamType::TokioTls(Box::new(tls_stream)),
+                                    limits,
+                                    keep_alive_timeout,
+                                    handler,
+                                    state,
+                                )
+                                .await;
                             }
                             Err(e) => {
                                 println!("TLS handshake error: {}", e);
@@ -94,10 +259,24 @@ impl Server {
     }
 }
 
-fn error(err: String) -> std::io::Error {
-    std::io::Error::new(std::io::ErrorKind::Other, err)
+fn parse_size(value: &str, option: &str) -> Result<usize, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| ConfigError::ParseError(format!("invalid size '{}' for '{}'", value, option)))
+}
+
+fn parse_seconds(value: &str, option: &str) -> Result<Duration, ConfigError> {
+    value.parse().map(Duration::from_secs).map_err(|_| {
+        ConfigError::ParseError(format!(
+            "invalid number of seconds '{}' for '{}'",
+            value, option
+        ))
+    })
 }
 
+fn error(err: String) -> std::io::Error {
+    std::io::Error::other(err)
+}
 
 // Load public certificate from file.
 fn load_certs(filename: &str) -> std::io::Result<Vec<rustls::Certificate>> {
@@ -3,7 +3,9 @@ use std::error::Error;
 use std::process;
 pub mod cli;
 pub mod error;
-use ironcladserver::cli::{Config, HelpMenu, ServerCommand, Version};
+use ironcladserver::cli::{Config, HelpMenu, ServerCommand, ServerConfigArguments, Version};
+use ironcladserver::psql::{db_psql_pool, PoolConfig};
+use ironcladserver::rate_limit::{LoginLimiter, PgLockoutStore, RateLimitConfig};
 use ironcladserver::Server;
 
 #[tokio::main]
@@ -22,6 +24,21 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 false => server.start_async().await?,
             }
         }
+        ServerCommand::Unlock => {
+            let args_opts_map = config.args_opts_map.unwrap();
+            let username = &args_opts_map[&ServerConfigArguments::Username];
+            // Lockouts outlive the server only in the database, so that's what gets cleared.
+            let Some(pool_config) = PoolConfig::from_env()? else {
+                eprintln!("'DATABASE_URL' is not set, lockouts are kept in the database.");
+                process::exit(1);
+            };
+            let store = PgLockoutStore::new(db_psql_pool(&pool_config)?);
+            let limiter = LoginLimiter::new(store, RateLimitConfig::default());
+            match limiter.unlock(username).await? {
+                true => println!("[  OK  ]     Unlocked user {:?}.", username),
+                false => println!("[  OK  ]     User {:?} had no failed logins.", username),
+            }
+        }
         ServerCommand::Help => {
             HelpMenu::show();
         }
@@ -0,0 +1,188 @@
+#![forbid(unsafe_code)]
+
+use crate::handler::Handler;
+use crate::headers::Headers;
+use crate::request::Request;
+use crate::response::Response;
+use futures::future::BoxFuture;
+use std::time::Instant;
+
+/// Code that runs around a handler, e.g. to add headers, log, authenticate or reject requests.
+/// It receives the request together with `next`, the rest of the chain, and decides whether
+/// and how to call it.
+///
+/// Wrap any handler (a whole `Router` or a single route's handler) with `.layer(...)`.
+/// The last layer added is the outermost one and sees the request first:
+///
+/// ```ignore
+/// let app = Router::new()
+///     .get("/", home)
+///     .get("/admin", admin.layer(RequireAdmin))
+///     .layer(SecurityHeaders::default())
+///     .layer(Logger);
+/// ```
+pub trait Middleware: Send + Sync + 'static {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Response>;
+}
+
+/// The remainder of the middleware chain, ending in the wrapped handler.
+pub struct Next<'a> {
+    handler: &'a dyn Handler,
+}
+
+impl<'a> Next<'a> {
+    pub async fn run(self, request: Request) -> Response {
+        self.handler.call(request).await
+    }
+}
+
+/// A handler wrapped in a middleware. It is a handler itself, so layers stack.
+pub struct Layered<H, M> {
+    inner: H,
+    middleware: M,
+}
+
+impl<H: Handler, M: Middleware> Handler for Layered<H, M> {
+    fn call(&self, request: Request) -> BoxFuture<'_, Response> {
+        let next = Next {
+            handler: &self.inner,
+        };
+        self.middleware.handle(request, next)
+    }
+}
+
+pub trait HandlerExt: Handler + Sized {
+    fn layer<M: Middleware>(self, middleware: M) -> Layered<Self, M> {
+        Layered {
+            inner: self,
+            middleware,
+        }
+    }
+}
+
+impl<H: Handler> HandlerExt for H {}
+
+/// Adds security related headers to every response that doesn't already set them,
+/// so a route can still override any of them.
+pub struct SecurityHeaders {
+    headers: Headers,
+}
+
+impl SecurityHeaders {
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders {
+            headers: Headers::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> SecurityHeaders {
+        self.headers.insert(name, value);
+        self
+    }
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> SecurityHeaders {
+        SecurityHeaders::new()
+            .header("X-Content-Type-Options", "nosniff")
+            .header("X-XSS-Protection", "1; mode=block")
+            .header("Content-Security-Policy", "default-src 'self'")
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let mut response = next.run(request).await;
+            for (name, value) in self.headers.iter() {
+                if !response.headers.contains(name) {
+                    response.headers.insert(name, value);
+                }
+            }
+            response
+        })
+    }
+}
+
+/// Prints one line per request with the response status and how long it took.
+/// Only the path is logged, query strings can carry credentials or tokens.
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let line = format!("{} {} {}", request.method, request.path(), request.version);
+            let started = Instant::now();
+            let response = next.run(request).await;
+            println!(
+                "Received request: {} -> {} ({} ms)",
+                line,
+                response.status,
+                started.elapsed().as_millis()
+            );
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::RequestParser;
+    use crate::router::Router;
+    use crate::status::StatusCode;
+
+    struct Tag(&'static str);
+
+    impl Middleware for Tag {
+        fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+            Box::pin(async move {
+                let mut response = next.run(request).await;
+                response.headers.append("X-Trace", self.0);
+                response
+            })
+        }
+    }
+
+    fn request(target: &str) -> Request {
+        let raw = format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", target);
+        RequestParser::new()
+            .parse(raw.as_bytes())
+            .unwrap()
+            .unwrap()
+            .0
+    }
+
+    #[tokio::test]
+    async fn layers_run_in_order_and_per_route() {
+        let ok = |_| async { Response::new(StatusCode::Ok) };
+        let app = Router::new()
+            .get("/plain", ok)
+            .get("/tagged", ok.layer(Tag("route")))
+            .layer(Tag("inner"))
+            .layer(Tag("outer"));
+
+        let response = app.call(request("/tagged")).await;
+        let trace: Vec<&str> = response.headers.get_all("X-Trace").collect();
+        assert_eq!(trace, ["route", "inner", "outer"]);
+        let response = app.call(request("/plain")).await;
+        let trace: Vec<&str> = response.headers.get_all("X-Trace").collect();
+        assert_eq!(trace, ["inner", "outer"]);
+    }
+
+    #[tokio::test]
+    async fn security_headers_keep_route_overrides() {
+        let app = (|_| async {
+            Response::builder(StatusCode::Ok)
+                .header("Content-Security-Policy", "default-src 'none'")
+                .empty()
+        })
+        .layer(SecurityHeaders::default());
+        let response = app.call(request("/")).await;
+        assert_eq!(
+            response.header("Content-Security-Policy"),
+            Some("default-src 'none'")
+        );
+        assert_eq!(response.header("X-Content-Type-Options"), Some("nosniff"));
+    }
+}
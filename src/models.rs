@@ -37,4 +37,4 @@ impl<'a> LoginPayload<'a> {
     pub fn new(username: &'a str, pwd: &'a str) -> Result<LoginPayload<'a>, Box<dyn Error + Send>> {
         Ok(LoginPayload { username, pwd })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,788 @@
+#![forbid(unsafe_code)]
+
+use crate::error::{FormError, HttpError};
+use crate::headers::{parse_parameters, Headers};
+use crate::request::{parse_header_line, Limits, Request};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+// Headers of a single part, e.g. Content-Disposition and Content-Type.
+const MAX_PART_HEADERS_SIZE: usize = 8 * 1024;
+// Spaces and tabs allowed after a boundary delimiter before its line ends.
+const MAX_TRANSPORT_PADDING: usize = 1024;
+
+/// Limits for `multipart/form-data` bodies.
+///
+/// Once a `MultipartConfig` is registered with `Server::with_state`, the server streams
+/// multipart bodies sent with a `Content-Length` while reading them, on every route:
+/// file parts go to `upload_dir` as they arrive, only text fields and part headers are
+/// kept in memory, and `max_body_size` replaces `Limits::max_body_size` for them.
+/// Without one, multipart bodies are read into memory under `Limits::max_body_size`
+/// (`-maxbody`) like any other. Handlers can apply stricter limits of their own with
+/// `Multipart::from_request`.
+#[derive(Debug, Clone)]
+pub struct MultipartConfig {
+    /// Where file parts are written. Defaults to the system temp directory.
+    pub upload_dir: PathBuf,
+    pub max_parts: usize,
+    /// Maximum number of file parts, 0 to only accept text fields.
+    pub max_files: usize,
+    pub max_file_size: usize,
+    pub max_field_size: usize,
+    /// Maximum size of the whole body, delimiters and part headers included.
+    pub max_body_size: usize,
+}
+
+impl Default for MultipartConfig {
+    fn default() -> MultipartConfig {
+        MultipartConfig {
+            upload_dir: std::env::temp_dir(),
+            max_parts: 32,
+            max_files: 8,
+            max_file_size: Limits::default().max_body_size,
+            max_field_size: 64 * 1024,
+            max_body_size: Limits::default().max_body_size,
+        }
+    }
+}
+
+/// A file part that was written to `upload_dir` under a random name.
+/// The file is deleted when this is dropped, unless `persist` moved it somewhere else first.
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub field_name: String,
+    /// The client's file name, reduced to its last path component. Never use it as a path
+    /// without checking it, it is whatever the client sent.
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub size: u64,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl UploadedFile {
+    /// Where the upload currently is on disk.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the upload to 'to', which should be on the same file system as `upload_dir`.
+    pub async fn persist(mut self, to: &Path) -> std::io::Result<()> {
+        fs::rename(&self.path, to).await?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// The text fields and files of a `multipart/form-data` body (RFC 7578).
+#[derive(Debug, Default)]
+pub struct Multipart {
+    fields: Vec<(String, String)>,
+    files: Vec<UploadedFile>,
+}
+
+impl Multipart {
+    /// The fields and files of the request's multipart body. When the server already
+    /// streamed the body to disk, this takes the result, checked against 'config' (its
+    /// `upload_dir` and `max_body_size` aside), and only the first call gets it.
+    /// Otherwise the body in memory is parsed, each file part copied to its own file in
+    /// `upload_dir`. Either way handlers can keep files with `persist`. Anything
+    /// malformed or over a limit is rejected before the handler sees it, and the files
+    /// written so far are removed again.
+    pub async fn from_request(
+        request: &Request,
+        config: &MultipartConfig,
+    ) -> Result<Multipart, FormError> {
+        if let Some(streamed) = &request.multipart.0 {
+            let multipart = streamed
+                .lock()
+                .expect("multipart lock poisoned")
+                .take()
+                .ok_or_else(|| {
+                    HttpError::BadRequest("multipart body was already read".to_string())
+                })?;
+            multipart.check(config)?;
+            return Ok(multipart);
+        }
+        let mut stream = MultipartStream::new(&boundary(&request.headers)?, config.clone());
+        stream.write(&request.body).await?;
+        Ok(stream.finish()?)
+    }
+
+    // Applies a handler's limits to a body streamed with the server's.
+    fn check(&self, config: &MultipartConfig) -> Result<(), HttpError> {
+        if self.fields.len() + self.files.len() > config.max_parts {
+            return Err(HttpError::BadRequest(format!(
+                "more than {} multipart parts",
+                config.max_parts
+            )));
+        }
+        if self.files.len() > config.max_files {
+            return Err(HttpError::BadRequest(format!(
+                "more than {} uploaded files",
+                config.max_files
+            )));
+        }
+        if self
+            .files
+            .iter()
+            .any(|file| file.size > config.max_file_size as u64)
+        {
+            return Err(HttpError::PayloadTooLarge(config.max_file_size));
+        }
+        if self
+            .fields
+            .iter()
+            .any(|(_, value)| value.len() > config.max_field_size)
+        {
+            return Err(HttpError::PayloadTooLarge(config.max_field_size));
+        }
+        Ok(())
+    }
+
+    /// The first text field called 'name'.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn files(&self) -> &[UploadedFile] {
+        &self.files
+    }
+
+    pub fn into_files(self) -> Vec<UploadedFile> {
+        self.files
+    }
+}
+
+/// Incremental parser for `multipart/form-data` bodies (RFC 7578) that writes file
+/// parts to `upload_dir` while their bytes arrive, so a file is never held in memory.
+/// Feed it the body in pieces of any size with `write`, then call `finish`.
+#[derive(Debug)]
+pub struct MultipartStream {
+    config: MultipartConfig,
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    state: StreamState,
+    part: Option<Part>,
+    size: usize,
+    multipart: Multipart,
+    skip_files: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StreamState {
+    Preamble,
+    Delimiter,
+    Headers,
+    Content,
+    Epilogue,
+}
+
+#[derive(Debug)]
+enum Part {
+    Field {
+        name: String,
+        value: Vec<u8>,
+    },
+    // The file is only created once content arrives, browsers send an empty part
+    // without a file name when no file was picked.
+    File {
+        upload: UploadedFile,
+        file: Option<File>,
+        named: bool,
+    },
+    // A file part of a stream that only reads text fields.
+    Skipped,
+}
+
+impl MultipartStream {
+    pub fn new(boundary: &str, config: MultipartConfig) -> MultipartStream {
+        MultipartStream {
+            config,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // The first delimiter may come right at the start, without the CRLF.
+            buffer: b"\r\n".to_vec(),
+            state: StreamState::Preamble,
+            part: None,
+            size: 0,
+            multipart: Multipart::default(),
+            skip_files: false,
+        }
+    }
+
+    // Like `new`, but file parts are passed over instead of written to disk.
+    fn fields_only(boundary: &str, config: MultipartConfig) -> MultipartStream {
+        MultipartStream {
+            skip_files: true,
+            ..MultipartStream::new(boundary, config)
+        }
+    }
+
+    /// Parses the next piece of the body, writing file content to disk. After an error
+    /// the stream is of no further use, dropping it removes the files written so far.
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), FormError> {
+        self.size += data.len();
+        if self.size > self.config.max_body_size {
+            return Err(HttpError::PayloadTooLarge(self.config.max_body_size).into());
+        }
+        self.buffer.extend_from_slice(data);
+
+        loop {
+            match self.state {
+                StreamState::Preamble => match find(&self.buffer, &self.delimiter, 0) {
+                    Some(index) => {
+                        self.buffer.drain(..index + self.delimiter.len());
+                        self.state = StreamState::Delimiter;
+                    }
+                    None => {
+                        // Anything before the first delimiter is ignored.
+                        self.split_off_content();
+                        return Ok(());
+                    }
+                },
+                StreamState::Delimiter => {
+                    if self.buffer.starts_with(b"--") {
+                        self.state = StreamState::Epilogue;
+                        continue;
+                    }
+                    // Transport padding after the delimiter is allowed, then the line must end.
+                    let padding = self
+                        .buffer
+                        .iter()
+                        .take_while(|b| **b == b' ' || **b == b'\t')
+                        .count();
+                    if self.buffer.len() < padding + 2 {
+                        if padding > MAX_TRANSPORT_PADDING {
+                            return Err(malformed_delimiter().into());
+                        }
+                        return Ok(());
+                    }
+                    if !self.buffer[padding..].starts_with(b"\r\n") {
+                        return Err(malformed_delimiter().into());
+                    }
+                    self.buffer.drain(..padding + 2);
+                    self.state = StreamState::Headers;
+                }
+                StreamState::Headers => {
+                    let head_end = match self.buffer.starts_with(b"\r\n") {
+                        true => Some((0, 2)),
+                        false => find(&self.buffer, b"\r\n\r\n", 0).map(|end| (end, end + 4)),
+                    };
+                    let Some((end, content_start)) = head_end else {
+                        if self.buffer.len() > MAX_PART_HEADERS_SIZE {
+                            return Err(HttpError::HeadersTooLarge(MAX_PART_HEADERS_SIZE).into());
+                        }
+                        return Ok(());
+                    };
+                    let headers = parse_part_headers(&self.buffer[..end])?;
+                    self.buffer.drain(..content_start);
+                    self.start_part(&headers)?;
+                    self.state = StreamState::Content;
+                }
+                StreamState::Content => match find(&self.buffer, &self.delimiter, 0) {
+                    Some(index) => {
+                        let rest = self.buffer.split_off(index + self.delimiter.len());
+                        let mut content = std::mem::replace(&mut self.buffer, rest);
+                        content.truncate(index);
+                        self.part_content(&content).await?;
+                        self.end_part().await?;
+                        self.state = StreamState::Delimiter;
+                    }
+                    None => {
+                        let content = self.split_off_content();
+                        self.part_content(&content).await?;
+                        return Ok(());
+                    }
+                },
+                StreamState::Epilogue => {
+                    self.buffer.clear();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// The fields and files, once the whole body was written.
+    pub fn finish(self) -> Result<Multipart, HttpError> {
+        match self.state {
+            StreamState::Epilogue => Ok(self.multipart),
+            _ => Err(HttpError::BadRequest(
+                "unterminated multipart body".to_string(),
+            )),
+        }
+    }
+
+    // Takes what is in the buffer, except a tail that could be the start of a delimiter.
+    fn split_off_content(&mut self) -> Vec<u8> {
+        let keep = self.buffer.len().min(self.delimiter.len() - 1);
+        let rest = self.buffer.split_off(self.buffer.len() - keep);
+        std::mem::replace(&mut self.buffer, rest)
+    }
+
+    fn start_part(&mut self, headers: &Headers) -> Result<(), HttpError> {
+        if self.multipart.fields.len() + self.multipart.files.len() >= self.config.max_parts {
+            return Err(HttpError::BadRequest(format!(
+                "more than {} multipart parts",
+                self.config.max_parts
+            )));
+        }
+        let disposition = headers.get("Content-Disposition").ok_or_else(|| {
+            HttpError::BadRequest("multipart part without Content-Disposition".to_string())
+        })?;
+        let (kind, params) = parse_parameters(disposition);
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let field_name = match param("name") {
+            Some(name) if kind.eq_ignore_ascii_case("form-data") => name.to_string(),
+            _ => {
+                return Err(HttpError::BadRequest(format!(
+                    "invalid Content-Disposition '{}'",
+                    disposition
+                )))
+            }
+        };
+
+        self.part = Some(match param("filename") {
+            Some(_) if self.skip_files => Part::Skipped,
+            Some(file_name) => Part::File {
+                upload: UploadedFile {
+                    field_name,
+                    file_name: sanitize_file_name(file_name),
+                    content_type: headers.get("Content-Type").map(str::to_string),
+                    size: 0,
+                    // A random name, so the client has no say in where the file ends up.
+                    path: self
+                        .config
+                        .upload_dir
+                        .join(format!("upload-{:032x}", rand::random::<u128>())),
+                    persisted: false,
+                },
+                file: None,
+                named: !file_name.is_empty(),
+            },
+            None => Part::Field {
+                name: field_name,
+                value: Vec::new(),
+            },
+        });
+        Ok(())
+    }
+
+    async fn part_content(&mut self, content: &[u8]) -> Result<(), FormError> {
+        if content.is_empty() {
+            return Ok(());
+        }
+        match self.part.as_mut().expect("content belongs to a part") {
+            Part::Field { value, .. } => {
+                if value.len() + content.len() > self.config.max_field_size {
+                    return Err(HttpError::PayloadTooLarge(self.config.max_field_size).into());
+                }
+                value.extend_from_slice(content);
+            }
+            Part::File { upload, file, .. } => {
+                if upload.size + content.len() as u64 > self.config.max_file_size as u64 {
+                    return Err(HttpError::PayloadTooLarge(self.config.max_file_size).into());
+                }
+                let file = match file {
+                    Some(file) => file,
+                    None => {
+                        check_files(&self.multipart, &self.config)?;
+                        file.insert(create_file(&upload.path).await?)
+                    }
+                };
+                file.write_all(content).await?;
+                upload.size += content.len() as u64;
+            }
+            Part::Skipped => {}
+        }
+        Ok(())
+    }
+
+    async fn end_part(&mut self) -> Result<(), FormError> {
+        match self.part.take() {
+            Some(Part::Field { name, value }) => {
+                let value = String::from_utf8(value)
+                    .map_err(|_| HttpError::BadRequest(format!("field '{}' is not UTF-8", name)))?;
+                self.multipart.fields.push((name, value));
+            }
+            Some(Part::File {
+                upload,
+                file: Some(mut file),
+                ..
+            }) => {
+                // tokio writes in the background, the flush makes sure the content is on
+                // disk before anyone opens the file by its path.
+                file.flush().await?;
+                self.multipart.files.push(upload);
+            }
+            Some(Part::File {
+                upload,
+                file: None,
+                named: true,
+            }) => {
+                check_files(&self.multipart, &self.config)?;
+                create_file(&upload.path).await?;
+                self.multipart.files.push(upload);
+            }
+            Some(Part::File {
+                file: None,
+                named: false,
+                ..
+            })
+            | Some(Part::Skipped)
+            | None => {}
+        }
+        Ok(())
+    }
+}
+
+fn check_files(multipart: &Multipart, config: &MultipartConfig) -> Result<(), HttpError> {
+    match multipart.files.len() >= config.max_files {
+        true => Err(HttpError::BadRequest(format!(
+            "more than {} uploaded files",
+            config.max_files
+        ))),
+        false => Ok(()),
+    }
+}
+
+fn malformed_delimiter() -> HttpError {
+    HttpError::BadRequest("malformed multipart delimiter".to_string())
+}
+
+/// A multipart body the server streamed to disk while reading the request, see
+/// `Multipart::from_request`. Clones of the request share it.
+#[derive(Clone, Default)]
+pub struct StreamedMultipart(Option<Arc<Mutex<Option<Multipart>>>>);
+
+impl StreamedMultipart {
+    pub(crate) fn new(multipart: Multipart) -> StreamedMultipart {
+        StreamedMultipart(Some(Arc::new(Mutex::new(Some(multipart)))))
+    }
+}
+
+impl fmt::Debug for StreamedMultipart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "StreamedMultipart(..)"),
+            None => write!(f, "StreamedMultipart(None)"),
+        }
+    }
+}
+
+impl PartialEq for StreamedMultipart {
+    fn eq(&self, other: &StreamedMultipart) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+
+/// The first text field called 'name', without writing any file parts to disk, e.g. for
+/// middleware that needs one field before the handler parses the whole form. A body in
+/// memory goes through the same `MultipartStream` as the handler's, with file parts
+/// skipped. None when there is no such field or the body is malformed.
+pub(crate) async fn text_field(request: &Request, name: &str) -> Option<String> {
+    if let Some(streamed) = &request.multipart.0 {
+        let multipart = streamed.lock().expect("multipart lock poisoned");
+        return multipart.as_ref()?.field(name).map(str::to_string);
+    }
+    let config = MultipartConfig {
+        max_files: 0,
+        // The body already is within the request's limits.
+        max_body_size: request.body.len(),
+        ..MultipartConfig::default()
+    };
+    let mut stream = MultipartStream::fields_only(&boundary(&request.headers).ok()?, config);
+    stream.write(&request.body).await.ok()?;
+    stream.finish().ok()?.field(name).map(str::to_string)
+}
+
+pub(crate) fn boundary(headers: &Headers) -> Result<String, HttpError> {
+    let content_type = headers.get("Content-Type").unwrap_or_default();
+    let (media_type, params) = parse_parameters(content_type);
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return Err(HttpError::UnsupportedMediaType(media_type.to_string()));
+    }
+    match params.into_iter().find(|(name, _)| name == "boundary") {
+        // RFC 2046, 5.1.1: 1 to 70 characters.
+        Some((_, boundary)) if !boundary.is_empty() && boundary.len() <= 70 => Ok(boundary),
+        _ => Err(HttpError::BadRequest(
+            "missing or invalid multipart boundary".to_string(),
+        )),
+    }
+}
+
+fn parse_part_headers(head: &[u8]) -> Result<Headers, HttpError> {
+    if head.len() > MAX_PART_HEADERS_SIZE {
+        return Err(HttpError::HeadersTooLarge(MAX_PART_HEADERS_SIZE));
+    }
+    let head = std::str::from_utf8(head)
+        .map_err(|_| HttpError::BadRequest("multipart part headers are not UTF-8".to_string()))?;
+
+    let mut headers = Headers::new();
+    for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+        let (name, value) = parse_header_line(line)?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+async fn create_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await
+}
+
+// Keeps only the last path component and drops control characters, so names like
+// '../../etc/passwd' or 'C:\evil.exe' are reduced to 'passwd' and 'evil.exe'.
+fn sanitize_file_name(file_name: &str) -> Option<String> {
+    let base = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base.chars().filter(|c| !c.is_control()).collect();
+    let cleaned = cleaned.trim();
+    match cleaned {
+        "" | "." | ".." => None,
+        _ => Some(cleaned.to_string()),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|index| index + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::{Limits, RequestParser};
+
+    fn request(content_type: &str, body: &str) -> Request {
+        let raw = format!(
+            "POST /upload HTTP/1.1\r\nHost: test\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+            content_type,
+            body.len(),
+            body
+        );
+        RequestParser::new()
+            .parse(raw.as_bytes())
+            .unwrap()
+            .unwrap()
+            .0
+    }
+
+    const BODY: &str = "preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"username\"\r\n\r\n\
+        mock1\r\n--XyZ  \r\n\
+        Content-Disposition: form-data; name=\"avatar\"; filename=\"../../etc/a \\\"b\\\".txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        line one\r\nline two\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"empty\"; filename=\"\"\r\n\r\n\
+        \r\n--XyZ--\r\nepilogue";
+
+    #[tokio::test]
+    async fn parses_fields_and_stores_files() {
+        let dir = std::env::temp_dir();
+        let config = MultipartConfig {
+            upload_dir: dir.clone(),
+            ..MultipartConfig::default()
+        };
+        let request = request("multipart/form-data; boundary=\"XyZ\"", BODY);
+        // Reading a single field leaves the file parts alone.
+        assert_eq!(
+            text_field(&request, "username").await.as_deref(),
+            Some("mock1")
+        );
+        assert_eq!(text_field(&request, "avatar").await, None);
+        let multipart = Multipart::from_request(&request, &config).await.unwrap();
+
+        assert_eq!(multipart.field("username"), Some("mock1"));
+        assert_eq!(multipart.files().len(), 1);
+        let file = &multipart.files()[0];
+        assert_eq!(file.field_name, "avatar");
+        assert_eq!(file.file_name.as_deref(), Some("a \"b\".txt"));
+        assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+        assert!(file.path().starts_with(&dir));
+        assert_eq!(std::fs::read(file.path()).unwrap(), b"line one\r\nline two");
+
+        let path = file.path().to_path_buf();
+        drop(multipart);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn parses_bodies_split_anywhere() {
+        let mut stream = MultipartStream::new("XyZ", MultipartConfig::default());
+        for byte in BODY.as_bytes() {
+            stream.write(std::slice::from_ref(byte)).await.unwrap();
+        }
+        let multipart = stream.finish().unwrap();
+        assert_eq!(multipart.field("username"), Some("mock1"));
+        assert_eq!(multipart.files().len(), 1);
+        let file = &multipart.files()[0];
+        assert_eq!(file.size, 18);
+        assert_eq!(std::fs::read(file.path()).unwrap(), b"line one\r\nline two");
+    }
+
+    // Feeds 'raw' in small pieces the way the connection loop does.
+    async fn stream(parser: &mut RequestParser, raw: &[u8]) -> (Request, Vec<u8>) {
+        let mut buffer = Vec::new();
+        for piece in raw.chunks(1000) {
+            buffer.extend_from_slice(piece);
+            loop {
+                if let Some((request, consumed)) = parser.parse(&buffer).unwrap() {
+                    buffer.drain(..consumed);
+                    return (request, buffer);
+                }
+                if !parser.stream_body(&mut buffer).await.unwrap() {
+                    break;
+                }
+            }
+            // The file content never piles up in the buffer.
+            assert!(buffer.len() < 2000);
+        }
+        panic!("incomplete request");
+    }
+
+    #[tokio::test]
+    async fn streams_uploads_past_the_body_limit() {
+        let config = MultipartConfig {
+            max_file_size: 64 * 1024,
+            max_body_size: 128 * 1024,
+            ..MultipartConfig::default()
+        };
+        let limits = Limits {
+            max_body_size: 1024,
+            ..Limits::default()
+        };
+        let content = "x".repeat(40 * 1024);
+        let body = format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\nabc\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"big.bin\"\r\n\r\n\
+            {}\r\n--XyZ--\r\n",
+            content
+        );
+        let head = format!(
+            "POST /upload HTTP/1.1\r\nHost: test\r\nContent-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        );
+        let raw = format!("{}{}GET / HTTP/1.1\r\n", head, body);
+
+        let mut parser = RequestParser::with_limits(limits).uploads(config.clone());
+        let (request, rest) = stream(&mut parser, raw.as_bytes()).await;
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+        assert!(request.body.is_empty());
+        assert_eq!(
+            text_field(&request, "csrf_token").await.as_deref(),
+            Some("abc")
+        );
+
+        // The handler's own limits still apply to what was streamed.
+        let no_files = MultipartConfig {
+            max_files: 0,
+            ..config.clone()
+        };
+        assert!(matches!(
+            Multipart::from_request(&request, &no_files).await,
+            Err(FormError::Http(HttpError::BadRequest(_)))
+        ));
+
+        let (request, _) = stream(&mut parser, raw.as_bytes()).await;
+        let multipart = Multipart::from_request(&request, &config).await.unwrap();
+        let file = &multipart.files()[0];
+        assert_eq!(file.file_name.as_deref(), Some("big.bin"));
+        assert_eq!(std::fs::read(file.path()).unwrap(), content.as_bytes());
+        // Only the first call gets the files.
+        assert!(Multipart::from_request(&request, &config).await.is_err());
+
+        let too_big = format!("{}{}", head, body).replace(
+            &format!("Content-Length: {}", body.len()),
+            "Content-Length: 200000",
+        );
+        assert_eq!(
+            RequestParser::new()
+                .uploads(config)
+                .parse(too_big.as_bytes()),
+            Err(HttpError::PayloadTooLarge(128 * 1024))
+        );
+    }
+
+    #[tokio::test]
+    async fn enforces_limits_and_rejects_malformed_bodies() {
+        let no_files = MultipartConfig {
+            max_files: 0,
+            ..MultipartConfig::default()
+        };
+        let small = MultipartConfig {
+            max_field_size: 3,
+            ..MultipartConfig::default()
+        };
+        let request_ok = request("multipart/form-data; boundary=XyZ", BODY);
+        assert!(matches!(
+            Multipart::from_request(&request_ok, &no_files).await,
+            Err(FormError::Http(HttpError::BadRequest(_)))
+        ));
+        assert!(matches!(
+            Multipart::from_request(&request_ok, &small).await,
+            Err(FormError::Http(HttpError::PayloadTooLarge(3)))
+        ));
+
+        let config = MultipartConfig::default();
+        for (content_type, body) in [
+            ("text/plain", BODY),
+            ("multipart/form-data", BODY),
+            ("multipart/form-data; boundary=XyZ", "--XyZ\r\n\r\nno end"),
+            (
+                "multipart/form-data; boundary=XyZ",
+                "--XyZ\r\nbroken\r\n\r\nx\r\n--XyZ--",
+            ),
+            (
+                "multipart/form-data; boundary=XyZ",
+                "--XyZ\r\n\r\nx\r\n--XyZ--",
+            ),
+        ] {
+            let result = Multipart::from_request(&request(content_type, body), &config).await;
+            assert!(result.is_err(), "{} {:?}", content_type, body);
+        }
+    }
+
+    #[test]
+    fn sanitizes_file_names() {
+        assert_eq!(
+            sanitize_file_name("C:\\evil.exe").as_deref(),
+            Some("evil.exe")
+        );
+        assert_eq!(sanitize_file_name("..").as_deref(), None);
+        assert_eq!(sanitize_file_name("dir/").as_deref(), None);
+        assert_eq!(sanitize_file_name("a\u{0}b.png").as_deref(), Some("ab.png"));
+    }
+}
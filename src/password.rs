@@ -0,0 +1,213 @@
+#![forbid(unsafe_code)]
+
+use crate::error::ConfigError;
+use argon2::password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use std::env;
+use subtle::ConstantTimeEq;
+
+/// Argon2id cost parameters for new password hashes. The defaults follow the OWASP
+/// recommendation (19 MiB, 2 iterations, 1 lane). They can be overridden with
+/// 'ARGON2_MEMORY_KIB', 'ARGON2_ITERATIONS' and 'ARGON2_PARALLELISM'.
+///
+/// Raising them doesn't invalidate stored hashes: those are verified with the
+/// parameters they were made with, and rehashed on the next successful login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordConfig {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> PasswordConfig {
+        PasswordConfig {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl PasswordConfig {
+    /// Reads the parameters from the environment, falling back to the defaults.
+    pub fn from_env() -> Result<PasswordConfig, ConfigError> {
+        let mut config = PasswordConfig::default();
+        for (name, value) in [
+            ("ARGON2_MEMORY_KIB", &mut config.memory_kib),
+            ("ARGON2_ITERATIONS", &mut config.iterations),
+            ("ARGON2_PARALLELISM", &mut config.parallelism),
+        ] {
+            if let Ok(setting) = env::var(name) {
+                *value = setting.parse().map_err(|_| {
+                    ConfigError::ParseError(format!("invalid number '{}' for '{}'", setting, name))
+                })?;
+            }
+        }
+        // Rejects values argon2 can't work with before the first login finds out.
+        config
+            .hasher()
+            .map_err(|e| ConfigError::ParseError(format!("invalid Argon2 parameters: {}", e)))?;
+        Ok(config)
+    }
+
+    fn hasher(&self) -> Result<Argon2<'static>, argon2::Error> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    // Whether a stored hash was made with something other than these parameters.
+    fn is_outdated(&self, hash: &PasswordHash) -> bool {
+        let params = match Params::try_from(hash) {
+            Ok(params) => params,
+            Err(_) => return true,
+        };
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.memory_kib
+            || params.t_cost() != self.iterations
+            || params.p_cost() != self.parallelism
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Invalid,
+    /// The password matches. 'needs_rehash' is set when the stored value is plaintext
+    /// or was hashed with other parameters, and should be replaced by `hash_password`.
+    Valid {
+        needs_rehash: bool,
+    },
+}
+
+/// Hashes a password with a random salt into a PHC string such as
+/// '$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>'. This is slow on purpose, call it
+/// from a blocking task.
+pub fn hash_password(
+    password: &str,
+    config: &PasswordConfig,
+) -> Result<String, password_hash::Error> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())?;
+    let hash = config.hasher()?.hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+/// Checks a password against a stored PHC string in constant time. Values that
+/// aren't PHC strings are plaintext passwords from before hashing was introduced,
+/// they are compared in constant time too and always need a rehash. They still cost
+/// a `dummy_verify`, so their users can't be told apart from unknown ones by timing.
+pub fn verify_password(
+    password: &str,
+    stored: &str,
+    config: &PasswordConfig,
+) -> Result<Verification, password_hash::Error> {
+    if !stored.starts_with('$') {
+        dummy_verify(password, config);
+        return Ok(
+            match bool::from(password.as_bytes().ct_eq(stored.as_bytes())) {
+                true => Verification::Valid { needs_rehash: true },
+                false => Verification::Invalid,
+            },
+        );
+    }
+    let hash = PasswordHash::new(stored)?;
+    // The stored hash brings its own parameters, the configured ones don't matter here.
+    match Argon2::default().verify_password(password.as_bytes(), &hash) {
+        Ok(()) => Ok(Verification::Valid {
+            needs_rehash: config.is_outdated(&hash),
+        }),
+        Err(password_hash::Error::Password) => Ok(Verification::Invalid),
+        Err(e) => Err(e),
+    }
+}
+
+/// Does the work of verifying a password against a hash made with 'config', for
+/// logins of users that don't exist. Without it the response would come back much
+/// sooner than for a wrong password, and tell which usernames are taken.
+pub fn dummy_verify(password: &str, config: &PasswordConfig) {
+    // Verifying is hashing again with the stored salt, so hashing costs the same.
+    let salt = SaltString::encode_b64(&[0; 16]).expect("16 bytes is a valid salt length");
+    if let Ok(hasher) = config.hasher() {
+        let _ = hasher.hash_password(password.as_bytes(), &salt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    // Cheap parameters so the tests stay fast.
+    fn config(iterations: u32) -> PasswordConfig {
+        PasswordConfig {
+            memory_kib: 1024,
+            iterations,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn hashes_and_verifies_passwords() {
+        let hash = hash_password("hunter2", &config(1)).unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert_ne!(hash, hash_password("hunter2", &config(1)).unwrap());
+
+        assert_eq!(
+            verify_password("hunter2", &hash, &config(1)),
+            Ok(Verification::Valid {
+                needs_rehash: false
+            })
+        );
+        assert_eq!(
+            verify_password("hunter3", &hash, &config(1)),
+            Ok(Verification::Invalid)
+        );
+        // Upgraded parameters still accept the old hash, but ask for a new one.
+        assert_eq!(
+            verify_password("hunter2", &hash, &config(2)),
+            Ok(Verification::Valid { needs_rehash: true })
+        );
+        assert!(verify_password(
+            "hunter2",
+            "$argon2id$v=19$m=x,t=1,p=1$c2FsdA$aGFzaA",
+            &config(1)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn upgrades_plaintext_passwords() {
+        assert_eq!(
+            verify_password("password1", "password1", &config(1)),
+            Ok(Verification::Valid { needs_rehash: true })
+        );
+        assert_eq!(
+            verify_password("password", "password1", &config(1)),
+            Ok(Verification::Invalid)
+        );
+    }
+
+    #[test]
+    fn plaintext_passwords_cost_a_hash() {
+        let config = PasswordConfig {
+            memory_kib: 2048,
+            iterations: 4,
+            parallelism: 1,
+        };
+        let started = Instant::now();
+        dummy_verify("wrong", &config);
+        let unknown_user = started.elapsed();
+
+        let started = Instant::now();
+        let verification = verify_password("wrong", "password1", &config);
+        let plaintext_user = started.elapsed();
+        assert_eq!(verification, Ok(Verification::Invalid));
+        // Same work, with a wide margin for a busy machine.
+        assert!(
+            plaintext_user > unknown_user / 2,
+            "{:?} vs {:?}",
+            plaintext_user,
+            unknown_user
+        );
+    }
+}
@@ -1,11 +1,92 @@
 use crate::models::{LoginPayload, User};
-use sqlx::postgres::PgPool;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::env;
+use std::time::Duration;
 extern crate rand;
-use crate::error::PsqlError;
+use crate::error::{ConfigError, PsqlError};
+use crate::password::{dummy_verify, hash_password, verify_password, PasswordConfig, Verification};
+
+/// Connection pool settings. 'DATABASE_URL' is required, the rest have defaults that
+/// can be overridden with 'DB_MAX_CONNECTIONS', 'DB_MIN_CONNECTIONS' and
+/// 'DB_ACQUIRE_TIMEOUT' (in seconds).
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub database_url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// How long a request waits for a free connection before giving up with a 503.
+    pub acquire_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl PoolConfig {
+    pub fn new(database_url: &str) -> PoolConfig {
+        PoolConfig {
+            database_url: database_url.to_string(),
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(3),
+            idle_timeout: Some(Duration::from_secs(600)),
+        }
+    }
+
+    /// Reads the settings from the environment or a local .env file, None when
+    /// 'DATABASE_URL' isn't set.
+    pub fn from_env() -> Result<Option<PoolConfig>, ConfigError> {
+        dotenv::dotenv().ok();
+        let Ok(database_url) = env::var("DATABASE_URL") else {
+            return Ok(None);
+        };
+        let mut config = PoolConfig::new(&database_url);
+        if let Some(max) = env_number("DB_MAX_CONNECTIONS")? {
+            config.max_connections = max as u32;
+        }
+        if let Some(min) = env_number("DB_MIN_CONNECTIONS")? {
+            config.min_connections = min as u32;
+        }
+        if let Some(secs) = env_number("DB_ACQUIRE_TIMEOUT")? {
+            config.acquire_timeout = Duration::from_secs(secs);
+        }
+        Ok(Some(config))
+    }
+}
+
+pub(crate) fn env_number(name: &str) -> Result<Option<u64>, ConfigError> {
+    match env::var(name) {
+        Ok(value) => value.parse().map(Some).map_err(|_| {
+            ConfigError::ParseError(format!("invalid number '{}' for '{}'", value, name))
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Creates the pool shared by all connections. It connects lazily, so the server can
+/// start (and serve everything else) while the database is down.
+pub fn db_psql_pool(config: &PoolConfig) -> Result<PgPool, PsqlError> {
+    let pool = PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(config.acquire_timeout)
+        .idle_timeout(config.idle_timeout)
+        .connect_lazy(&config.database_url)?;
+    Ok(pool)
+}
+
+/// Checks that a connection can be made and used.
+pub async fn db_psql_health_check(pool: &PgPool) -> Result<(), PsqlError> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
 
 /// Create new user in postgresql database. Connection details to the db in Dev are provided via
 /// an environment variable (local .env file), to make it easier for testing.
-pub async fn db_psql_create_user<'a>(pool: &PgPool, user: User<'a>) -> Result<i32, PsqlError> {
+/// The password is stored as an Argon2id hash, never in plaintext.
+pub async fn db_psql_create_user<'a>(
+    pool: &PgPool,
+    user: User<'a>,
+    config: &PasswordConfig,
+) -> Result<i32, PsqlError> {
+    let pwd_hash = hash_blocking(user.pwd.to_string(), *config).await?;
     let new_user = sqlx::query!(
         r#"
         INSERT INTO users (id, username, pwd)
@@ -14,7 +95,7 @@ pub async fn db_psql_create_user<'a>(pool: &PgPool, user: User<'a>) -> Result<i3
         "#,
         user.id,
         user.username,
-        user.pwd
+        pwd_hash
     )
     .fetch_one(pool)
     .await?;
@@ -22,11 +103,16 @@ pub async fn db_psql_create_user<'a>(pool: &PgPool, user: User<'a>) -> Result<i3
     Ok(new_user.id)
 }
 
+/// Checks a user's password against the stored hash. Plaintext passwords left from
+/// before hashing, and hashes made with older parameters, are replaced on success.
+/// Unknown users cost the same password work as known ones, so the time it takes
+/// doesn't tell them apart.
 pub async fn db_psql_validate_user<'a>(
     pool: &PgPool,
     user: &LoginPayload<'a>,
+    config: &PasswordConfig,
 ) -> Result<(), PsqlError> {
-    let result = sqlx::query!(
+    let stored = sqlx::query!(
         r#"
             SELECT pwd
             FROM users
@@ -34,19 +120,72 @@ pub async fn db_psql_validate_user<'a>(
         "#,
         user.username
     )
-    .fetch_one(pool)
-    .await?;
+    .fetch_optional(pool)
+    .await?
+    .map(|result| result.pwd)
+    .filter(|pwd| !pwd.is_empty());
 
-    if result.pwd.is_empty() {
+    let (pwd, config) = (user.pwd.to_string(), *config);
+    let Some(stored) = stored else {
+        tokio::task::spawn_blocking(move || dummy_verify(&pwd, &config))
+            .await
+            .map_err(|e| PsqlError::PasswordHash(e.to_string()))?;
         return Err(PsqlError::SqlxError(sqlx::Error::RowNotFound));
-    }
-    if result.pwd == user.pwd {
-        Ok(())
-    } else {
-        Err(PsqlError::PasswordMismatch)
+    };
+    let verified = stored.clone();
+    let verification =
+        tokio::task::spawn_blocking(move || verify_password(&pwd, &verified, &config))
+            .await
+            .map_err(|e| PsqlError::PasswordHash(e.to_string()))??;
+
+    match verification {
+        Verification::Invalid => Err(PsqlError::PasswordMismatch),
+        Verification::Valid { needs_rehash } => {
+            if needs_rehash {
+                // The login itself succeeded, a failed upgrade is retried next time.
+                if let Err(e) = db_psql_rehash(pool, user, &stored, &config).await {
+                    eprintln!(
+                        "Failed to rehash the password of '{}': {}",
+                        user.username, e
+                    );
+                }
+            }
+            Ok(())
+        }
     }
 }
 
+// Only replaces the value we verified, in case the password changed in the meantime.
+async fn db_psql_rehash<'a>(
+    pool: &PgPool,
+    user: &LoginPayload<'a>,
+    verified: &str,
+    config: &PasswordConfig,
+) -> Result<(), PsqlError> {
+    let pwd_hash = hash_blocking(user.pwd.to_string(), *config).await?;
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET pwd = $1
+            WHERE username = $2 AND pwd = $3
+        "#,
+        pwd_hash,
+        user.username,
+        verified
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Argon2 takes tens of milliseconds of CPU on purpose, too long for an async task.
+async fn hash_blocking(pwd: String, config: PasswordConfig) -> Result<String, PsqlError> {
+    tokio::task::spawn_blocking(move || hash_password(&pwd, &config))
+        .await
+        .map_err(|e| PsqlError::PasswordHash(e.to_string()))?
+        .map_err(PsqlError::from)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -76,7 +215,23 @@ mod tests {
         let test_user = User::new(Some(test_id), test_username.as_str(), test_pwd.as_str())
             .expect("Failed to create new user instance");
 
-        assert!(db_psql_create_user(&test_pool, test_user).await.is_ok());
+        let config = PasswordConfig::default();
+        assert!(db_psql_create_user(&test_pool, test_user, &config)
+            .await
+            .is_ok());
+
+        // Stored hashed, and the hash verifies.
+        let stored = sqlx::query!("SELECT pwd FROM users WHERE id = $1", test_id)
+            .fetch_one(&test_pool)
+            .await
+            .expect("Failed to read the new user")
+            .pwd;
+        assert!(stored.starts_with("$argon2id$"));
+        let login = LoginPayload::new(test_username.as_str(), test_pwd.as_str())
+            .expect("Failed to create new user instance");
+        assert!(db_psql_validate_user(&test_pool, &login, &config)
+            .await
+            .is_ok());
     }
 
     #[tokio::test]
@@ -96,7 +251,21 @@ mod tests {
         let mock_user = LoginPayload::new(mock_username.as_str(), mock_pwd.as_str())
             .expect("Failed to create new 'mock' user instance");
 
-        assert!(db_psql_validate_user(&test_pool, &mock_user).await.is_ok());
+        assert!(
+            db_psql_validate_user(&test_pool, &mock_user, &PasswordConfig::default())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn psql_pool_reports_unavailable_database() {
+        let mut config = PoolConfig::new("postgres://postgres@127.0.0.1:1/ironclad");
+        config.acquire_timeout = Duration::from_millis(500);
+        let pool = db_psql_pool(&config).expect("Failed to create psql pool");
+
+        let err = db_psql_health_check(&pool).await.unwrap_err();
+        assert!(err.is_unavailable(), "{}", err);
     }
 
     #[tokio::test]
@@ -114,8 +283,10 @@ mod tests {
         let dummy_user = LoginPayload::new(dummy_username.as_str(), dummy_pwd.as_str())
             .expect("Failed to create new 'dummy' user instance");
 
-        assert!(db_psql_validate_user(&test_pool, &dummy_user)
-            .await
-            .is_err());
+        assert!(
+            db_psql_validate_user(&test_pool, &dummy_user, &PasswordConfig::default())
+                .await
+                .is_err()
+        );
     }
-}
\ No newline at end of file
+}
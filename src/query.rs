@@ -0,0 +1,131 @@
+#![forbid(unsafe_code)]
+
+use crate::error::HttpError;
+use percent_encoding::percent_decode_str;
+use serde::de::DeserializeOwned;
+use url::form_urlencoded;
+
+/// The decoded 'key=value' pairs of a query string or an urlencoded form body,
+/// in the order they were sent. Keys may repeat, e.g. '?tag=a&tag=b'.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    /// Splits and decodes a raw query string (without the leading '?'). '+' is a space and
+    /// percent-encoded bytes that aren't valid UTF-8 become U+FFFD, like browsers do.
+    pub fn parse(query: &str) -> QueryParams {
+        QueryParams::parse_bytes(query.as_bytes())
+    }
+
+    /// Same as `parse`, for an `application/x-www-form-urlencoded` body.
+    pub fn parse_bytes(input: &[u8]) -> QueryParams {
+        QueryParams {
+            pairs: form_urlencoded::parse(input).into_owned().collect(),
+        }
+    }
+
+    /// The first value sent for 'key'.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).next()
+    }
+
+    pub fn get_all<'a, 'k>(&'a self, key: &'k str) -> impl Iterator<Item = &'a str> + 'k
+    where
+        'a: 'k,
+    {
+        self.pairs
+            .iter()
+            .filter(move |(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(name, _)| name == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+/// Deserializes a raw query string into 'T'. Missing or malformed fields are the client's
+/// fault, so they turn into a 400.
+pub fn deserialize_query<T: DeserializeOwned>(query: &str) -> Result<T, HttpError> {
+    serde_urlencoded::from_str(query)
+        .map_err(|e| HttpError::BadRequest(format!("Invalid query string: {}", e)))
+}
+
+/// Deserializes an urlencoded form body into 'T', see `deserialize_query`.
+pub fn deserialize_form<T: DeserializeOwned>(body: &[u8]) -> Result<T, HttpError> {
+    serde_urlencoded::from_bytes(body)
+        .map_err(|e| HttpError::BadRequest(format!("Invalid form body: {}", e)))
+}
+
+/// Percent-decodes one path segment. Unlike in queries, '+' stays a '+'.
+/// Returns None when the decoded bytes aren't valid UTF-8.
+pub fn decode_path_segment(segment: &str) -> Option<String> {
+    percent_decode_str(segment)
+        .decode_utf8()
+        .ok()
+        .map(|decoded| decoded.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[test]
+    fn decodes_pairs_and_repeated_keys() {
+        let params =
+            QueryParams::parse("q=rust+web%20server&tag=a&tag=b&empty=&flag&caf%C3%A9=%E2%9C%93");
+        assert_eq!(params.get("q"), Some("rust web server"));
+        assert_eq!(params.get_all("tag").collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(params.get("empty"), Some(""));
+        assert_eq!(params.get("flag"), Some(""));
+        assert_eq!(params.get("café"), Some("✓"));
+        assert_eq!(params.get("missing"), None);
+        assert_eq!(params.len(), 6);
+        assert_eq!(QueryParams::parse("bad=%ff").get("bad"), Some("\u{fffd}"));
+    }
+
+    #[test]
+    fn deserializes_into_structs() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Search {
+            q: String,
+            page: u32,
+            lang: Option<String>,
+        }
+        let search: Search = deserialize_query("q=a%26b&page=2").unwrap();
+        assert_eq!(
+            search,
+            Search {
+                q: "a&b".to_string(),
+                page: 2,
+                lang: None
+            }
+        );
+        assert!(deserialize_query::<Search>("q=x&page=two").is_err());
+        assert!(deserialize_query::<Search>("page=1").is_err());
+    }
+
+    #[test]
+    fn decodes_path_segments() {
+        assert_eq!(decode_path_segment("a%20b+c").as_deref(), Some("a b+c"));
+        assert_eq!(decode_path_segment("%2F").as_deref(), Some("/"));
+        assert_eq!(decode_path_segment("%ff"), None);
+    }
+}
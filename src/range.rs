@@ -0,0 +1,314 @@
+#![forbid(unsafe_code)]
+
+use crate::conditional::{http_date, EntityTag};
+use crate::request::{Method, Request};
+use crate::response::Body;
+use bytes::Bytes;
+use futures::stream;
+use std::collections::VecDeque;
+use std::io::SeekFrom;
+use std::time::SystemTime;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+// More ranges than this in one request is not a resumed download or a video player seeking,
+// so the header is ignored and the whole file sent instead.
+const MAX_RANGES: usize = 16;
+
+/// An inclusive range of byte positions, like in `Content-Range: bytes 0-499/1234`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end < self.start
+    }
+
+    pub fn content_range(&self, complete_len: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, complete_len)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No usable `Range` header: send the whole representation with 200.
+    Full,
+    /// Sorted, with overlapping and adjacent ranges merged.
+    Partial(Vec<ByteRange>),
+    /// Syntactically fine, but nothing in it overlaps the representation: 416.
+    Unsatisfiable,
+}
+
+/// Works out which part of a representation of 'len' bytes the request asks for (RFC 9110, 14).
+/// Only GET requests have ranges, and a failed `If-Range` turns them into a full response.
+pub fn range_request(
+    request: &Request,
+    len: u64,
+    etag: Option<&EntityTag>,
+    last_modified: Option<SystemTime>,
+) -> RangeRequest {
+    if request.method != Method::Get {
+        return RangeRequest::Full;
+    }
+    let Some(range) = request.header("Range") else {
+        return RangeRequest::Full;
+    };
+    if let Some(if_range) = request.header("If-Range") {
+        if !if_range_matches(if_range, etag, last_modified) {
+            return RangeRequest::Full;
+        }
+    }
+    parse_range(range, len)
+}
+
+// If-Range only matches a strong entity tag, or exactly the Last-Modified date we'd send.
+fn if_range_matches(
+    if_range: &str,
+    etag: Option<&EntityTag>,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    match EntityTag::parse(if_range) {
+        Some(tag) => etag.is_some_and(|etag| etag.strong_eq(&tag)),
+        None => last_modified.is_some_and(|time| http_date(time) == if_range.trim()),
+    }
+}
+
+/// Parses a `Range` value such as 'bytes=0-99, 200-, -500'. A header we don't understand
+/// is ignored rather than rejected, as RFC 9110 asks.
+pub fn parse_range(value: &str, len: u64) -> RangeRequest {
+    let Some((unit, specs)) = value.split_once('=') else {
+        return RangeRequest::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return RangeRequest::Full;
+    }
+
+    if specs.split(',').all(|spec| spec.trim().is_empty()) {
+        return RangeRequest::Full;
+    }
+    let mut ranges = Vec::new();
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let range = if first.is_empty() {
+            // '-n': the last 'n' bytes.
+            let Some(suffix) = parse_position(last) else {
+                return RangeRequest::Full;
+            };
+            if suffix == 0 || len == 0 {
+                continue;
+            }
+            ByteRange {
+                start: len.saturating_sub(suffix),
+                end: len - 1,
+            }
+        } else {
+            // 'a-b' and 'a-': from 'a', up to 'b' or the end.
+            let Some(start) = parse_position(first) else {
+                return RangeRequest::Full;
+            };
+            let end = match last.is_empty() {
+                true => None,
+                false => match parse_position(last) {
+                    Some(end) if end >= start => Some(end),
+                    _ => return RangeRequest::Full,
+                },
+            };
+            if start >= len {
+                continue;
+            }
+            ByteRange {
+                start,
+                end: end.map_or(len - 1, |end| end.min(len - 1)),
+            }
+        };
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+    if ranges.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+    RangeRequest::Partial(coalesce(ranges))
+}
+
+fn parse_position(value: &str) -> Option<u64> {
+    match !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+        true => value.parse().ok(),
+        false => None,
+    }
+}
+
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(previous) if range.start <= previous.end.saturating_add(1) => {
+                previous.end = previous.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+enum Piece {
+    Bytes(Bytes),
+    FileRange { start: u64, len: u64 },
+}
+
+/// Where the bytes of a ranged response come from.
+pub enum RangeSource {
+    File(File),
+    Bytes(Bytes),
+}
+
+/// A `multipart/byteranges` body with one part per range. File ranges are read as the
+/// body is sent. Returns the body and the boundary that goes into its Content-Type.
+pub fn byteranges_body(
+    source: RangeSource,
+    ranges: &[ByteRange],
+    content_type: &str,
+    complete_len: u64,
+) -> (Body, String) {
+    let boundary = format!("{:032x}", rand::random::<u128>());
+    let mut pieces = VecDeque::new();
+    for range in ranges {
+        let head = format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            boundary,
+            content_type,
+            range.content_range(complete_len)
+        );
+        pieces.push_back(Piece::Bytes(Bytes::from(head)));
+        pieces.push_back(match &source {
+            RangeSource::File(_) => Piece::FileRange {
+                start: range.start,
+                len: range.len(),
+            },
+            RangeSource::Bytes(content) => {
+                Piece::Bytes(content.slice(range.start as usize..=range.end as usize))
+            }
+        });
+    }
+    pieces.push_back(Piece::Bytes(Bytes::from(format!(
+        "\r\n--{}--\r\n",
+        boundary
+    ))));
+    let file = match source {
+        RangeSource::File(file) => Some(file),
+        RangeSource::Bytes(_) => None,
+    };
+
+    let body = Body::stream(stream::unfold(
+        (file, pieces),
+        |(mut file, mut pieces)| async move {
+            loop {
+                match pieces.pop_front()? {
+                    Piece::Bytes(bytes) => return Some((bytes, (file, pieces))),
+                    Piece::FileRange { len: 0, .. } => continue,
+                    Piece::FileRange { start, len } => {
+                        let reader = file.as_mut()?;
+                        let mut chunk = vec![0; len.min(16 * 1024) as usize];
+                        let read = match reader.seek(SeekFrom::Start(start)).await {
+                            Ok(_) => reader.read(&mut chunk).await,
+                            Err(e) => Err(e),
+                        };
+                        match read {
+                            Ok(bytes_read) if bytes_read > 0 => {
+                                chunk.truncate(bytes_read);
+                                pieces.push_front(Piece::FileRange {
+                                    start: start + bytes_read as u64,
+                                    len: len - bytes_read as u64,
+                                });
+                                return Some((Bytes::from(chunk), (file, pieces)));
+                            }
+                            // The file shrank or can't be read, all we can do is stop early.
+                            Ok(_) => {
+                                eprintln!("File ended before the requested range");
+                                return None;
+                            }
+                            Err(e) => {
+                                eprintln!("Error reading file range: {}", e);
+                                return None;
+                            }
+                        }
+                    }
+                }
+            }
+        },
+    ));
+    (body, boundary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(ranges: &[(u64, u64)]) -> RangeRequest {
+        RangeRequest::Partial(
+            ranges
+                .iter()
+                .map(|&(start, end)| ByteRange { start, end })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parses_range_specs() {
+        assert_eq!(parse_range("bytes=0-99", 1000), partial(&[(0, 99)]));
+        assert_eq!(parse_range("bytes=900-", 1000), partial(&[(900, 999)]));
+        assert_eq!(parse_range("bytes=-100", 1000), partial(&[(900, 999)]));
+        assert_eq!(parse_range("bytes=-5000", 1000), partial(&[(0, 999)]));
+        assert_eq!(parse_range("bytes=990-2000", 1000), partial(&[(990, 999)]));
+        assert_eq!(
+            parse_range("Bytes=500-599, 0-9,5-20 ,600-610", 1000),
+            partial(&[(0, 20), (500, 610)])
+        );
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-0,1000-", 1000), partial(&[(0, 0)]));
+    }
+
+    #[test]
+    fn ignores_invalid_headers() {
+        for value in [
+            "bytes=5-1",
+            "bytes=a-b",
+            "bytes=1-2-3",
+            "bytes= 1-2x",
+            "items=0-1",
+            "bytes",
+            "bytes=",
+            "bytes=+1-2",
+        ] {
+            assert_eq!(parse_range(value, 1000), RangeRequest::Full, "{}", value);
+        }
+        let many = (0..20)
+            .map(|i| format!("{}-{}", i * 10, i * 10))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(
+            parse_range(&format!("bytes={}", many), 1000),
+            RangeRequest::Full
+        );
+    }
+}
@@ -0,0 +1,627 @@
+#![forbid(unsafe_code)]
+
+use crate::error::{ConfigError, PsqlError};
+use crate::psql::env_number;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// At most this many addresses get a bucket. When a new one comes in at the cap, the
+// buckets that refilled completely are dropped, and then the least recently used ones
+// until a quarter is free again, so the cleanup only runs every so often.
+const MAX_TRACKED_ADDRESSES: usize = 10_000;
+// The longest duration accepted from the environment, far below where the lockout
+// doubling and the date math would overflow.
+const MAX_ENV_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Thresholds for login attempts. Every setting can be overridden from the environment,
+/// durations in seconds: 'LOGIN_IP_BURST', 'LOGIN_IP_PER_MINUTE', 'LOGIN_MAX_FAILURES',
+/// 'LOGIN_FAILURE_WINDOW', 'LOGIN_LOCKOUT' and 'LOGIN_MAX_LOCKOUT'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Attempts an IP address can make in a row.
+    pub ip_burst: u32,
+    /// Attempts an IP address gets back every minute, up to 'ip_burst'.
+    pub ip_per_minute: u32,
+    /// Failed logins of a username before it is locked.
+    pub max_failures: u32,
+    /// Failures are forgotten after this long without any.
+    pub failure_window: Duration,
+    /// The first lockout. Every further failure doubles it, up to 'max_lockout'.
+    pub lockout: Duration,
+    pub max_lockout: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            ip_burst: 10,
+            ip_per_minute: 5,
+            max_failures: 5,
+            failure_window: Duration::from_secs(15 * 60),
+            lockout: Duration::from_secs(60),
+            max_lockout: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Reads the thresholds from the environment, falling back to the defaults.
+    pub fn from_env() -> Result<RateLimitConfig, ConfigError> {
+        let mut config = RateLimitConfig::default();
+        for (name, value) in [
+            ("LOGIN_IP_BURST", &mut config.ip_burst),
+            ("LOGIN_IP_PER_MINUTE", &mut config.ip_per_minute),
+            ("LOGIN_MAX_FAILURES", &mut config.max_failures),
+        ] {
+            if let Some(number) = env_number(name)? {
+                *value = match u32::try_from(number) {
+                    Ok(0) => {
+                        return Err(ConfigError::ParseError(format!(
+                            "'{}' must be at least 1",
+                            name
+                        )))
+                    }
+                    Ok(number) => number,
+                    Err(_) => {
+                        return Err(ConfigError::ParseError(format!(
+                            "'{}' can't be more than {}",
+                            name,
+                            u32::MAX
+                        )))
+                    }
+                };
+            }
+        }
+        for (name, value) in [
+            ("LOGIN_FAILURE_WINDOW", &mut config.failure_window),
+            ("LOGIN_LOCKOUT", &mut config.lockout),
+            ("LOGIN_MAX_LOCKOUT", &mut config.max_lockout),
+        ] {
+            if let Some(secs) = env_number(name)? {
+                let duration = Duration::from_secs(secs);
+                if duration > MAX_ENV_DURATION {
+                    return Err(ConfigError::ParseError(format!(
+                        "'{}' can't be more than {} seconds",
+                        name,
+                        MAX_ENV_DURATION.as_secs()
+                    )));
+                }
+                *value = duration;
+            }
+        }
+        Ok(config)
+    }
+
+    /// How long a username is locked after its 'failures'th failed login in a row.
+    pub fn lockout_for(&self, failures: u32) -> Option<Duration> {
+        if failures < self.max_failures {
+            return None;
+        }
+        let doublings = (failures - self.max_failures).min(16);
+        let lockout = self
+            .lockout
+            .checked_mul(2u32.pow(doublings))
+            .unwrap_or(self.max_lockout);
+        Some(lockout.min(self.max_lockout))
+    }
+}
+
+/// The failed logins of one username.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lockout {
+    pub failures: u32,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl Lockout {
+    // Failures stop counting once there were none for a whole window, counted from the
+    // end of the lockout so a long lockout doesn't reset the count by itself.
+    fn is_forgotten(&self, window_start: DateTime<Utc>) -> bool {
+        self.last_failure_at
+            .max(self.locked_until.unwrap_or(self.last_failure_at))
+            < window_start
+    }
+}
+
+/// Where failed logins are kept, so lockouts survive restarts and apply on every server.
+pub trait LockoutStore: Send + Sync + 'static {
+    fn load<'a>(&'a self, username: &'a str) -> BoxFuture<'a, Result<Option<Lockout>, PsqlError>>;
+    /// Counts a failed login and returns the count. Failures forgotten by 'window_start'
+    /// are dropped first.
+    fn record_failure<'a>(
+        &'a self,
+        username: &'a str,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<u32, PsqlError>>;
+    fn lock<'a>(
+        &'a self,
+        username: &'a str,
+        until: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), PsqlError>>;
+    /// Forgets the failures and any lockout. Returns whether there was anything to forget.
+    fn clear<'a>(&'a self, username: &'a str) -> BoxFuture<'a, Result<bool, PsqlError>>;
+    /// Removes the records forgotten by 'window_start'.
+    fn purge_expired(&self, window_start: DateTime<Utc>) -> BoxFuture<'_, Result<(), PsqlError>>;
+}
+
+/// Keeps failed logins in memory, for development and single instances without a
+/// database. They are lost on restart.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLockoutStore {
+    lockouts: Arc<Mutex<HashMap<String, Lockout>>>,
+}
+
+impl MemoryLockoutStore {
+    pub fn new() -> MemoryLockoutStore {
+        MemoryLockoutStore::default()
+    }
+
+    fn lockouts(&self) -> std::sync::MutexGuard<'_, HashMap<String, Lockout>> {
+        self.lockouts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl LockoutStore for MemoryLockoutStore {
+    fn load<'a>(&'a self, username: &'a str) -> BoxFuture<'a, Result<Option<Lockout>, PsqlError>> {
+        let lockout = self.lockouts().get(username).cloned();
+        Box::pin(async move { Ok(lockout) })
+    }
+
+    fn record_failure<'a>(
+        &'a self,
+        username: &'a str,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<u32, PsqlError>> {
+        let mut lockouts = self.lockouts();
+        let lockout = lockouts.entry(username.to_string()).or_insert(Lockout {
+            failures: 0,
+            last_failure_at: now,
+            locked_until: None,
+        });
+        if lockout.is_forgotten(window_start) {
+            lockout.failures = 0;
+        }
+        lockout.failures += 1;
+        lockout.last_failure_at = now;
+        let failures = lockout.failures;
+        Box::pin(async move { Ok(failures) })
+    }
+
+    fn lock<'a>(
+        &'a self,
+        username: &'a str,
+        until: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), PsqlError>> {
+        if let Some(lockout) = self.lockouts().get_mut(username) {
+            lockout.locked_until = Some(until);
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn clear<'a>(&'a self, username: &'a str) -> BoxFuture<'a, Result<bool, PsqlError>> {
+        let cleared = self.lockouts().remove(username).is_some();
+        Box::pin(async move { Ok(cleared) })
+    }
+
+    fn purge_expired(&self, window_start: DateTime<Utc>) -> BoxFuture<'_, Result<(), PsqlError>> {
+        self.lockouts()
+            .retain(|_, lockout| !lockout.is_forgotten(window_start));
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Keeps failed logins in the 'login_lockouts' table.
+#[derive(Debug, Clone)]
+pub struct PgLockoutStore {
+    pool: PgPool,
+}
+
+impl PgLockoutStore {
+    pub fn new(pool: PgPool) -> PgLockoutStore {
+        PgLockoutStore { pool }
+    }
+}
+
+impl LockoutStore for PgLockoutStore {
+    fn load<'a>(&'a self, username: &'a str) -> BoxFuture<'a, Result<Option<Lockout>, PsqlError>> {
+        Box::pin(async move {
+            let row = sqlx::query!(
+                r#"
+                    SELECT failures, last_failure_at, locked_until
+                    FROM login_lockouts
+                    WHERE username = $1
+                "#,
+                username
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+            Ok(row.map(|row| Lockout {
+                failures: row.failures.max(0) as u32,
+                last_failure_at: row.last_failure_at,
+                locked_until: row.locked_until,
+            }))
+        })
+    }
+
+    fn record_failure<'a>(
+        &'a self,
+        username: &'a str,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<u32, PsqlError>> {
+        // A single statement, so concurrent attempts can't both read the old count.
+        Box::pin(async move {
+            let failures = sqlx::query_scalar!(
+                r#"
+                    INSERT INTO login_lockouts (username, failures, last_failure_at)
+                    VALUES ( $1, 1, $2 )
+                    ON CONFLICT (username) DO UPDATE
+                    SET failures = CASE
+                            WHEN GREATEST(login_lockouts.last_failure_at, login_lockouts.locked_until) < $3
+                            THEN 1
+                            ELSE login_lockouts.failures + 1
+                        END,
+                        last_failure_at = $2
+                    RETURNING failures
+                "#,
+                username,
+                now,
+                window_start
+            )
+            .fetch_one(&self.pool)
+            .await?;
+            Ok(failures.max(0) as u32)
+        })
+    }
+
+    fn lock<'a>(
+        &'a self,
+        username: &'a str,
+        until: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), PsqlError>> {
+        Box::pin(async move {
+            sqlx::query!(
+                "UPDATE login_lockouts SET locked_until = $2 WHERE username = $1",
+                username,
+                until
+            )
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn clear<'a>(&'a self, username: &'a str) -> BoxFuture<'a, Result<bool, PsqlError>> {
+        Box::pin(async move {
+            let result = sqlx::query!("DELETE FROM login_lockouts WHERE username = $1", username)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn purge_expired(&self, window_start: DateTime<Utc>) -> BoxFuture<'_, Result<(), PsqlError>> {
+        Box::pin(async move {
+            sqlx::query!(
+                r#"
+                    DELETE FROM login_lockouts
+                    WHERE GREATEST(last_failure_at, locked_until) < $1
+                "#,
+                window_start
+            )
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+// A token bucket: 'ip_burst' attempts, refilled at 'ip_per_minute'.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Limits login attempts per IP address and per username. Addresses get a token bucket
+/// in memory, so a single client can't try a password list quickly. Usernames are locked
+/// for a while after repeated failures, so a password list spread over many addresses
+/// doesn't get far either. Unknown usernames are counted the same, a lockout mustn't
+/// tell which users exist.
+#[derive(Clone)]
+pub struct LoginLimiter {
+    config: RateLimitConfig,
+    store: Arc<dyn LockoutStore>,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl LoginLimiter {
+    pub fn new(store: impl LockoutStore, config: RateLimitConfig) -> LoginLimiter {
+        LoginLimiter {
+            config,
+            store: Arc::new(store),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    /// Takes an attempt from the address's bucket. Returns how long to wait when it's
+    /// empty, None when the attempt may go ahead. IPv6 addresses share a bucket per /64,
+    /// since a single client usually has a whole /64 to pick addresses from.
+    pub fn check_ip(&self, ip: IpAddr) -> Option<Duration> {
+        let burst = self.config.ip_burst.max(1) as f64;
+        let per_second = self.config.ip_per_minute.max(1) as f64 / 60.0;
+        let refilled = |bucket: &Bucket, now: Instant| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * per_second).min(burst)
+        };
+
+        let now = Instant::now();
+        let key = bucket_key(ip);
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_TRACKED_ADDRESSES && !buckets.contains_key(&key) {
+            buckets.retain(|_, bucket| refilled(bucket, now) < burst);
+            let keep = MAX_TRACKED_ADDRESSES * 3 / 4;
+            if buckets.len() > keep {
+                let mut updated: Vec<Instant> =
+                    buckets.values().map(|bucket| bucket.updated).collect();
+                let (_, cutoff, _) = updated.select_nth_unstable(buckets.len() - keep - 1);
+                let cutoff = *cutoff;
+                buckets.retain(|_, bucket| bucket.updated > cutoff);
+            }
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = refilled(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+        Some(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+    }
+
+    /// How long the username stays locked, None when it isn't.
+    pub async fn check_user(&self, username: &str) -> Result<Option<Duration>, PsqlError> {
+        let Some(Lockout {
+            locked_until: Some(until),
+            ..
+        }) = self.store.load(username).await?
+        else {
+            return Ok(None);
+        };
+        Ok((until - Utc::now()).to_std().ok())
+    }
+
+    /// Counts a failed login, and locks the username once there were too many.
+    /// Returns the lockout it started, if any.
+    pub async fn record_failure(&self, username: &str) -> Result<Option<Duration>, PsqlError> {
+        let now = Utc::now();
+        let failures = self
+            .store
+            .record_failure(username, now, self.window_start(now))
+            .await?;
+        let Some(lockout) = self.config.lockout_for(failures) else {
+            return Ok(None);
+        };
+        // Locked for as long as dates go when the lockout is too long to add.
+        let until = chrono::Duration::from_std(lockout)
+            .ok()
+            .and_then(|lockout| now.checked_add_signed(lockout))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        self.store.lock(username, until).await?;
+        Ok(Some(lockout))
+    }
+
+    /// A successful login forgets the username's failures. It also clears out other
+    /// records nobody will look at again.
+    pub async fn record_success(&self, username: &str) -> Result<(), PsqlError> {
+        self.store.clear(username).await?;
+        self.store
+            .purge_expired(self.window_start(Utc::now()))
+            .await
+    }
+
+    /// Lifts a lockout early. Returns whether the username had any failures recorded.
+    pub async fn unlock(&self, username: &str) -> Result<bool, PsqlError> {
+        self.store.clear(username).await
+    }
+
+    fn window_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        // Nothing is forgotten when the window reaches back further than dates go.
+        chrono::Duration::from_std(self.config.failure_window)
+            .ok()
+            .and_then(|window| now.checked_sub_signed(window))
+            .unwrap_or(DateTime::<Utc>::UNIX_EPOCH)
+    }
+}
+
+// IPv4 addresses as they are, also when mapped into IPv6, and IPv6 addresses by their
+// /64 prefix.
+fn bucket_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & !(u64::MAX as u128))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            ip_burst: 3,
+            ip_per_minute: 60,
+            max_failures: 2,
+            ..RateLimitConfig::default()
+        }
+    }
+
+    #[test]
+    fn limits_attempts_per_address() {
+        let limiter = LoginLimiter::new(MemoryLockoutStore::new(), config());
+        let (client, other) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        for _ in 0..3 {
+            assert_eq!(limiter.check_ip(client), None);
+        }
+        let retry_after = limiter.check_ip(client).unwrap();
+        assert!(retry_after > Duration::from_millis(900) && retry_after <= Duration::from_secs(1));
+        assert_eq!(limiter.check_ip(other), None);
+    }
+
+    #[test]
+    fn limits_ipv6_addresses_per_prefix() {
+        let limiter = LoginLimiter::new(MemoryLockoutStore::new(), config());
+        for ip in ["2001:db8::1", "2001:db8::2", "2001:db8::ffff:1:2"] {
+            assert_eq!(limiter.check_ip(ip.parse().unwrap()), None);
+        }
+        assert!(limiter.check_ip("2001:db8::3".parse().unwrap()).is_some());
+        assert_eq!(limiter.check_ip("2001:db8:0:1::1".parse().unwrap()), None);
+
+        let mapped: IpAddr = "::ffff:10.0.0.1".parse().unwrap();
+        let v4: IpAddr = "10.0.0.1".parse().unwrap();
+        assert_eq!(bucket_key(mapped), v4);
+    }
+
+    #[test]
+    fn caps_tracked_addresses() {
+        let limiter = LoginLimiter::new(MemoryLockoutStore::new(), config());
+        let address = |n: u32| IpAddr::from(std::net::Ipv4Addr::from(0x0a00_0000 + n));
+        for n in 0..MAX_TRACKED_ADDRESSES as u32 + 100 {
+            assert_eq!(limiter.check_ip(address(n)), None);
+            assert!(limiter.buckets.lock().unwrap().len() <= MAX_TRACKED_ADDRESSES);
+        }
+        // The most recent addresses are still tracked.
+        let last = address(MAX_TRACKED_ADDRESSES as u32 + 99);
+        assert_eq!(limiter.check_ip(last), None);
+        assert_eq!(limiter.check_ip(last), None);
+        assert!(limiter.check_ip(last).is_some());
+    }
+
+    #[test]
+    fn doubles_lockouts() {
+        let config = config();
+        assert_eq!(config.lockout_for(1), None);
+        assert_eq!(config.lockout_for(2), Some(Duration::from_secs(60)));
+        assert_eq!(config.lockout_for(4), Some(Duration::from_secs(240)));
+        assert_eq!(config.lockout_for(100), Some(Duration::from_secs(3600)));
+
+        let huge = RateLimitConfig {
+            lockout: Duration::MAX / 4,
+            max_lockout: Duration::MAX,
+            ..config
+        };
+        assert_eq!(huge.lockout_for(2), Some(Duration::MAX / 4));
+        assert_eq!(huge.lockout_for(10), Some(Duration::MAX));
+    }
+
+    #[tokio::test]
+    async fn survives_durations_too_long_for_dates() {
+        let config = RateLimitConfig {
+            failure_window: Duration::MAX,
+            lockout: Duration::MAX,
+            max_lockout: Duration::MAX,
+            ..config()
+        };
+        let limiter = LoginLimiter::new(MemoryLockoutStore::new(), config);
+        assert_eq!(limiter.record_failure("mock1").await.unwrap(), None);
+        assert_eq!(
+            limiter.record_failure("mock1").await.unwrap(),
+            Some(Duration::MAX)
+        );
+        assert!(limiter.check_user("mock1").await.unwrap().is_some());
+        limiter.record_success("mock1").await.unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_range_settings() {
+        env::set_var(
+            "LOGIN_LOCKOUT",
+            (MAX_ENV_DURATION.as_secs() + 1).to_string(),
+        );
+        assert!(RateLimitConfig::from_env().is_err());
+        env::set_var("LOGIN_LOCKOUT", "120");
+        assert_eq!(
+            RateLimitConfig::from_env().unwrap().lockout,
+            Duration::from_secs(120)
+        );
+        env::remove_var("LOGIN_LOCKOUT");
+
+        // Too big for a u32, it must not wrap around to 0.
+        env::set_var("LOGIN_MAX_FAILURES", "4294967296");
+        assert!(RateLimitConfig::from_env().is_err());
+        env::set_var("LOGIN_MAX_FAILURES", "0");
+        assert!(RateLimitConfig::from_env().is_err());
+        env::remove_var("LOGIN_MAX_FAILURES");
+    }
+
+    #[tokio::test]
+    async fn locks_usernames_after_failures() {
+        let store = MemoryLockoutStore::new();
+        let limiter = LoginLimiter::new(store.clone(), config());
+        assert_eq!(limiter.record_failure("mock1").await.unwrap(), None);
+        assert_eq!(limiter.check_user("mock1").await.unwrap(), None);
+        assert_eq!(
+            limiter.record_failure("mock1").await.unwrap(),
+            Some(Duration::from_secs(60))
+        );
+        let retry_after = limiter.check_user("mock1").await.unwrap().unwrap();
+        assert!(retry_after > Duration::from_secs(55));
+
+        assert!(limiter.unlock("mock1").await.unwrap());
+        assert_eq!(limiter.check_user("mock1").await.unwrap(), None);
+        assert!(!limiter.unlock("mock1").await.unwrap());
+
+        // Failures from long ago start the count over.
+        let long_ago = Utc::now() - chrono::Duration::hours(2);
+        let window_start = Utc::now() - chrono::Duration::minutes(15);
+        assert_eq!(
+            store
+                .record_failure("mock2", long_ago, long_ago)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(limiter.record_failure("mock2").await.unwrap(), None);
+        store.purge_expired(window_start).await.unwrap();
+        assert_eq!(store.load("mock2").await.unwrap().unwrap().failures, 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_lockouts_in_postgres() {
+        dotenv::dotenv().ok();
+        let database_url =
+            env::var("DATABASE_URL").expect("Failed to read test 'database_url' env variable.");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+        let username = format!("lockout-test-{}", hex::encode(rand::random::<[u8; 8]>()));
+        let limiter = LoginLimiter::new(PgLockoutStore::new(pool.clone()), config());
+
+        assert_eq!(limiter.record_failure(&username).await.unwrap(), None);
+        assert!(limiter.record_failure(&username).await.unwrap().is_some());
+        // Another server sharing the database sees the lockout.
+        let other = LoginLimiter::new(PgLockoutStore::new(pool), config());
+        assert!(other.check_user(&username).await.unwrap().is_some());
+
+        limiter.record_success(&username).await.unwrap();
+        assert_eq!(other.check_user(&username).await.unwrap(), None);
+        assert!(!other.unlock(&username).await.unwrap());
+    }
+}
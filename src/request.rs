@@ -0,0 +1,713 @@
+#![forbid(unsafe_code)]
+
+use crate::chunked::ChunkedDecoder;
+use crate::cookie::CookieJar;
+use crate::error::{FormError, HttpError};
+use crate::headers::{parse_parameters, Headers};
+use crate::multipart::{self, MultipartConfig, MultipartStream, StreamedMultipart};
+use crate::query::{deserialize_form, deserialize_query, QueryParams};
+use crate::router::PathParams;
+use crate::state::AppState;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    Extension(String),
+}
+
+impl Method {
+    /// Parses a request method token. Unknown but well-formed methods are kept as
+    /// `Method::Extension`, so routing decides what to do with them.
+    pub fn parse(token: &str) -> Result<Method, HttpError> {
+        if token.is_empty() || !token.bytes().all(is_tchar) {
+            return Err(HttpError::BadRequest(format!("invalid method '{}'", token)));
+        }
+        let method = match token {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "CONNECT" => Method::Connect,
+            "OPTIONS" => Method::Options,
+            "TRACE" => Method::Trace,
+            "PATCH" => Method::Patch,
+            other => Method::Extension(other.to_string()),
+        };
+        Ok(method)
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+            Method::Extension(token) => token.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    fn parse(token: &str) -> Result<Version, HttpError> {
+        match token {
+            "HTTP/1.0" => Ok(Version::Http10),
+            "HTTP/1.1" => Ok(Version::Http11),
+            other => {
+                let bytes = other.as_bytes();
+                // Something that looks like 'HTTP/x.y' is a real version we just don't speak.
+                if bytes.len() == 8
+                    && other.starts_with("HTTP/")
+                    && bytes[5].is_ascii_digit()
+                    && bytes[6] == b'.'
+                    && bytes[7].is_ascii_digit()
+                {
+                    Err(HttpError::VersionNotSupported(other.to_string()))
+                } else {
+                    Err(HttpError::BadRequest(format!(
+                        "invalid version '{}'",
+                        other
+                    )))
+                }
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: Method,
+    pub target: String,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// Trailer fields sent after a chunked body. Always empty for other requests.
+    pub trailers: Headers,
+    /// Filled in by the `Router` from the matched route pattern.
+    pub params: PathParams,
+    /// The server's shared state, set when the request is read from a connection.
+    pub state: AppState,
+    /// The client's address, set when the request is read from a connection.
+    pub peer_addr: Option<SocketAddr>,
+    /// A `multipart/form-data` body the parser streamed to disk instead of into `body`,
+    /// read it with `Multipart::from_request`.
+    pub multipart: StreamedMultipart,
+}
+
+impl Request {
+    /// The path component of the request target, i.e. everything before the '?'.
+    /// It is still percent-encoded, route params are decoded by the `Router`.
+    pub fn path(&self) -> &str {
+        match self.target.split_once('?') {
+            Some((path, _)) => path,
+            None => self.target.as_str(),
+        }
+    }
+
+    /// The raw query string of the request target, without the leading '?'.
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    /// The decoded query parameters, empty when the target has no query.
+    pub fn query_params(&self) -> QueryParams {
+        QueryParams::parse(self.query().unwrap_or_default())
+    }
+
+    /// Deserializes the query parameters into 'T', e.g. a struct deriving `Deserialize`.
+    pub fn query_as<T: DeserializeOwned>(&self) -> Result<T, HttpError> {
+        deserialize_query(self.query().unwrap_or_default())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The cookies sent with the request.
+    pub fn cookies(&self) -> CookieJar {
+        CookieJar::from_request(self)
+    }
+
+    /// The value of type 'T' registered with `Server::with_state`.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get()
+    }
+
+    /// The lowercased media type of the body, without parameters, e.g. 'application/json'.
+    pub fn media_type(&self) -> Option<String> {
+        self.headers
+            .get("Content-Type")
+            .map(|value| parse_parameters(value).0.to_ascii_lowercase())
+    }
+
+    /// The fields of an `application/x-www-form-urlencoded` body.
+    pub fn form(&self) -> Result<QueryParams, HttpError> {
+        self.expect_form()?;
+        Ok(QueryParams::parse_bytes(&self.body))
+    }
+
+    /// Deserializes an `application/x-www-form-urlencoded` body into 'T'.
+    pub fn form_as<T: DeserializeOwned>(&self) -> Result<T, HttpError> {
+        self.expect_form()?;
+        deserialize_form(&self.body)
+    }
+
+    fn expect_form(&self) -> Result<(), HttpError> {
+        match self.media_type() {
+            Some(media_type) if media_type == "application/x-www-form-urlencoded" => Ok(()),
+            other => Err(HttpError::UnsupportedMediaType(
+                other.unwrap_or_else(|| "none".to_string()),
+            )),
+        }
+    }
+
+    /// Whether the client wants the connection kept open after this request.
+    /// HTTP/1.1 connections persist unless closed explicitly, HTTP/1.0 ones only on request.
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 => !self.headers.has_token("Connection", "close"),
+            Version::Http10 => self.headers.has_token("Connection", "keep-alive"),
+        }
+    }
+}
+
+/// Upper bounds applied while reading a request, so a client can't make us buffer
+/// an unbounded amount of data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum size in bytes of the request line plus headers. Larger heads get a 431.
+    pub max_header_size: usize,
+    /// Maximum size in bytes of the request body. Larger bodies get a 413.
+    pub max_body_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_header_size: 8 * 1024,
+            max_body_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Incremental HTTP/1.x request parser.
+///
+/// Feed it everything received so far for the current request. It returns `Ok(None)` while
+/// more bytes are needed and remembers how far it got, so bytes are only scanned once.
+/// Once a request is complete it returns it together with the number of bytes consumed
+/// and resets itself for the next request on the connection.
+///
+/// With `uploads` set, `multipart/form-data` bodies sent with a `Content-Length` are
+/// streamed instead: `parse` keeps returning `Ok(None)` while `stream_body` moves the
+/// body out of the buffer and writes its file parts to disk.
+#[derive(Debug, Default)]
+pub struct RequestParser {
+    limits: Limits,
+    uploads: Option<MultipartConfig>,
+    scanned: usize,
+    head: Option<ParsedHead>,
+}
+
+#[derive(Debug)]
+struct ParsedHead {
+    request: Request,
+    head_len: usize,
+    framing: BodyFraming,
+}
+
+// How the end of the body is found, see RFC 9112, 6.3.
+#[derive(Debug)]
+enum BodyFraming {
+    Length(usize),
+    Chunked(ChunkedDecoder),
+    // The bytes of the body not streamed yet.
+    Streamed(usize, Box<MultipartStream>),
+}
+
+impl RequestParser {
+    pub fn new() -> RequestParser {
+        RequestParser::default()
+    }
+
+    pub fn with_limits(limits: Limits) -> RequestParser {
+        RequestParser {
+            limits,
+            ..RequestParser::default()
+        }
+    }
+
+    /// Whether the parser is still waiting for the end of a request head.
+    pub fn reading_head(&self) -> bool {
+        self.head.is_none()
+    }
+
+    /// Streams multipart bodies to disk with 'config', see `stream_body`.
+    pub fn uploads(mut self, config: MultipartConfig) -> RequestParser {
+        self.uploads = Some(config);
+        self
+    }
+
+    pub fn parse(&mut self, buf: &[u8]) -> Result<Option<(Request, usize)>, HttpError> {
+        if self.head.is_none() {
+            match self.find_head_end(buf)? {
+                Some(head_end) => {
+                    let head = parse_head(
+                        &buf[..head_end],
+                        head_end,
+                        self.limits,
+                        self.uploads.as_ref(),
+                    )?;
+                    self.head = Some(head);
+                }
+                None => return Ok(None),
+            }
+        }
+
+        let head = self.head.as_mut().expect("head was parsed above");
+        let total_len = match &mut head.framing {
+            BodyFraming::Length(length) => {
+                let total_len = head.head_len + *length;
+                if buf.len() < total_len {
+                    return Ok(None);
+                }
+                head.request.body = buf[head.head_len..total_len].to_vec();
+                total_len
+            }
+            BodyFraming::Chunked(decoder) => match decoder.decode(&buf[head.head_len..])? {
+                Some(body_len) => {
+                    head.request.body = decoder.take_body();
+                    head.request.trailers = decoder.take_trailers();
+                    head.head_len + body_len
+                }
+                None => return Ok(None),
+            },
+            BodyFraming::Streamed(0, _) => head.head_len,
+            BodyFraming::Streamed(..) => return Ok(None),
+        };
+
+        let mut parsed = self.head.take().expect("head was parsed above");
+        if let BodyFraming::Streamed(_, stream) = parsed.framing {
+            parsed.request.multipart = StreamedMultipart::new(stream.finish()?);
+        }
+        self.scanned = 0;
+        Ok(Some((parsed.request, total_len)))
+    }
+
+    /// Moves the body bytes of a streamed multipart request out of 'buf', which starts
+    /// with the request head, and writes them to the upload. Returns whether there were
+    /// any, `parse` then returns the request once it is complete.
+    pub async fn stream_body(&mut self, buf: &mut Vec<u8>) -> Result<bool, FormError> {
+        let Some(ParsedHead {
+            head_len,
+            framing: BodyFraming::Streamed(remaining, stream),
+            ..
+        }) = &mut self.head
+        else {
+            return Ok(false);
+        };
+        let available = (*remaining).min(buf.len().saturating_sub(*head_len));
+        if available == 0 {
+            return Ok(false);
+        }
+        let body: Vec<u8> = buf.drain(*head_len..*head_len + available).collect();
+        *remaining -= available;
+        stream.write(&body).await?;
+        Ok(true)
+    }
+
+    // Looks for the empty line that ends the head, rejecting bare CR or LF on the way.
+    fn find_head_end(&mut self, buf: &[u8]) -> Result<Option<usize>, HttpError> {
+        let start = leading_empty_lines(buf);
+        let mut index = self.scanned.max(start);
+
+        while index < buf.len() {
+            match buf[index] {
+                b'\n' if index == 0 || buf[index - 1] != b'\r' => {
+                    return Err(HttpError::BadRequest("bare LF in request head".to_string()));
+                }
+                b'\r' if index + 1 < buf.len() && buf[index + 1] != b'\n' => {
+                    return Err(HttpError::BadRequest("bare CR in request head".to_string()));
+                }
+                b'\n' if index >= start + 3 && &buf[index - 3..=index] == b"\r\n\r\n" => {
+                    return match index + 1 > self.limits.max_header_size {
+                        true => Err(HttpError::HeadersTooLarge(self.limits.max_header_size)),
+                        false => Ok(Some(index + 1)),
+                    };
+                }
+                _ => {}
+            }
+            index += 1;
+        }
+        // Leading empty lines count too, or a client could send them forever.
+        if buf.len() > self.limits.max_header_size {
+            return Err(HttpError::HeadersTooLarge(self.limits.max_header_size));
+        }
+        // Step back so a trailing CR is checked again once its successor arrives.
+        self.scanned = buf.len().saturating_sub(1);
+        Ok(None)
+    }
+}
+
+// Servers should ignore empty lines received before the request line (RFC 9112, 2.2).
+fn leading_empty_lines(buf: &[u8]) -> usize {
+    let mut start = 0;
+    while buf[start..].starts_with(b"\r\n") {
+        start += 2;
+    }
+    start
+}
+
+fn parse_head(
+    buf: &[u8],
+    head_len: usize,
+    limits: Limits,
+    uploads: Option<&MultipartConfig>,
+) -> Result<ParsedHead, HttpError> {
+    let head = std::str::from_utf8(&buf[leading_empty_lines(buf)..])
+        .map_err(|_| HttpError::BadRequest("request head is not valid UTF-8".to_string()))?;
+    let mut lines = head.trim_end_matches("\r\n").split("\r\n");
+
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => {
+            return Err(HttpError::BadRequest(format!(
+                "malformed request line '{}'",
+                request_line
+            )))
+        }
+    };
+    let method = Method::parse(method)?;
+    if target.is_empty() || !target.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(HttpError::BadRequest(format!(
+            "invalid target '{}'",
+            target
+        )));
+    }
+    let version = Version::parse(version)?;
+
+    let mut headers = Headers::new();
+    for line in lines {
+        let (name, value) = parse_header_line(line)?;
+        headers.append(name, value);
+    }
+
+    if version == Version::Http11 && headers.get_all("Host").count() != 1 {
+        return Err(HttpError::BadRequest(
+            "HTTP/1.1 requests need exactly one Host header".to_string(),
+        ));
+    }
+    // Multipart bodies of a known length are streamed to disk, with their own size limit.
+    let streamed = match (uploads, multipart::boundary(&headers)) {
+        (Some(config), Ok(boundary)) if !headers.contains("Transfer-Encoding") => {
+            Some((config, boundary))
+        }
+        _ => None,
+    };
+    let framing = match streamed {
+        Some((config, boundary)) => match content_length(&headers)? {
+            0 => BodyFraming::Length(0),
+            length if length > config.max_body_size => {
+                return Err(HttpError::PayloadTooLarge(config.max_body_size))
+            }
+            length => {
+                let stream = MultipartStream::new(&boundary, config.clone());
+                BodyFraming::Streamed(length, Box::new(stream))
+            }
+        },
+        None => body_framing(&headers, version, limits)?,
+    };
+
+    Ok(ParsedHead {
+        request: Request {
+            method,
+            target: target.to_string(),
+            version,
+            headers,
+            body: Vec::new(),
+            trailers: Headers::new(),
+            params: PathParams::default(),
+            state: AppState::default(),
+            peer_addr: None,
+            multipart: StreamedMultipart::default(),
+        },
+        head_len,
+        framing,
+    })
+}
+
+/// Splits a header (or trailer) line into name and value, rejecting obsolete line folding,
+/// whitespace before the colon and control characters in the value.
+pub(crate) fn parse_header_line(line: &str) -> Result<(&str, &str), HttpError> {
+    if line.starts_with(' ') || line.starts_with('\t') {
+        return Err(HttpError::BadRequest(
+            "obsolete header line folding is not allowed".to_string(),
+        ));
+    }
+    let (name, value) = line
+        .split_once(':')
+        .ok_or_else(|| HttpError::BadRequest(format!("malformed header line '{}'", line)))?;
+    if name.is_empty() || !name.bytes().all(is_tchar) {
+        return Err(HttpError::BadRequest(format!(
+            "invalid header name '{}'",
+            name
+        )));
+    }
+    let value = value.trim_matches(|c| c == ' ' || c == '\t');
+    if value.chars().any(|c| c.is_ascii_control() && c != '\t') {
+        return Err(HttpError::BadRequest(format!(
+            "invalid value for header '{}'",
+            name
+        )));
+    }
+    Ok((name, value))
+}
+
+// A request carrying both Transfer-Encoding and Content-Length, or a Transfer-Encoding we
+// can't decode, is refused outright: guessing which framing a proxy in front of us used
+// is how request smuggling happens.
+fn body_framing(
+    headers: &Headers,
+    version: Version,
+    limits: Limits,
+) -> Result<BodyFraming, HttpError> {
+    if !headers.contains("Transfer-Encoding") {
+        let length = content_length(headers)?;
+        if length > limits.max_body_size {
+            return Err(HttpError::PayloadTooLarge(limits.max_body_size));
+        }
+        return Ok(BodyFraming::Length(length));
+    }
+
+    if version == Version::Http10 {
+        return Err(HttpError::BadRequest(
+            "Transfer-Encoding is not allowed in HTTP/1.0 requests".to_string(),
+        ));
+    }
+    if headers.contains("Content-Length") {
+        return Err(HttpError::BadRequest(
+            "both Transfer-Encoding and Content-Length are present".to_string(),
+        ));
+    }
+    let codings: Vec<&str> = headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(|coding| coding.trim())
+        .collect();
+    match codings.last() {
+        Some(last) if last.eq_ignore_ascii_case("chunked") => {}
+        _ => {
+            return Err(HttpError::BadRequest(
+                "chunked must be the final transfer coding".to_string(),
+            ))
+        }
+    }
+    if codings.len() > 1 {
+        return Err(HttpError::NotImplemented(format!(
+            "transfer coding '{}'",
+            codings[0]
+        )));
+    }
+    Ok(BodyFraming::Chunked(ChunkedDecoder::new(
+        limits.max_body_size,
+        limits.max_header_size,
+    )))
+}
+
+// Repeated Content-Length fields are only acceptable when they all agree.
+fn content_length(headers: &Headers) -> Result<usize, HttpError> {
+    let mut length: Option<usize> = None;
+    for value in headers.get_all("Content-Length") {
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(HttpError::BadRequest(format!(
+                "invalid Content-Length '{}'",
+                value
+            )));
+        }
+        let parsed: usize = value
+            .parse()
+            .map_err(|_| HttpError::BadRequest(format!("invalid Content-Length '{}'", value)))?;
+        match length {
+            Some(previous) if previous != parsed => {
+                return Err(HttpError::BadRequest(
+                    "conflicting Content-Length headers".to_string(),
+                ))
+            }
+            _ => length = Some(parsed),
+        }
+    }
+    Ok(length.unwrap_or(0))
+}
+
+// Token characters as defined in RFC 9110, 5.6.2.
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(raw: &[u8]) -> Result<Option<(Request, usize)>, HttpError> {
+        RequestParser::new().parse(raw)
+    }
+
+    #[test]
+    fn parses_request_with_query_and_http10() {
+        let (request, consumed) = parse_all(b"GET /?x=1 HTTP/1.0\r\nAccept: */*\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path(), "/");
+        assert_eq!(request.query(), Some("x=1"));
+        assert_eq!(request.version, Version::Http10);
+        assert_eq!(request.header("accept"), Some("*/*"));
+        assert_eq!(consumed, 35);
+    }
+
+    #[test]
+    fn parses_incrementally() {
+        let raw = b"PURGE /cache HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello";
+        let mut parser = RequestParser::new();
+        for end in 1..raw.len() {
+            assert!(parser.parse(&raw[..end]).unwrap().is_none());
+        }
+        let (request, consumed) = parser.parse(raw).unwrap().unwrap();
+        assert_eq!(request.method, Method::Extension("PURGE".to_string()));
+        assert_eq!(request.body, b"hello");
+        assert_eq!(consumed, raw.len());
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        let bad: [&[u8]; 6] = [
+            b"GET / HTTP/1.1\nHost: a\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost : a\r\n\r\n",
+            b"GET  / HTTP/1.1\r\nHost: a\r\n\r\n",
+            b"GET / HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n",
+        ];
+        for raw in bad {
+            assert!(matches!(parse_all(raw), Err(HttpError::BadRequest(_))));
+        }
+        assert!(matches!(
+            parse_all(b"GET / HTTP/2.0\r\n\r\n"),
+            Err(HttpError::VersionNotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn parses_chunked_body_with_trailers() {
+        let raw = b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nChecksum: abc\r\n\r\nGET";
+        let (request, consumed) = parse_all(raw).unwrap().unwrap();
+        assert_eq!(request.body, b"hello, world");
+        assert_eq!(request.trailers.get("checksum"), Some("abc"));
+        assert_eq!(&raw[consumed..], b"GET");
+    }
+
+    #[test]
+    fn rejects_ambiguous_framing() {
+        let both = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\
+            Transfer-Encoding: chunked\r\n\r\n";
+        assert!(matches!(parse_all(both), Err(HttpError::BadRequest(_))));
+        let not_last = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked, gzip\r\n\r\n";
+        assert!(matches!(parse_all(not_last), Err(HttpError::BadRequest(_))));
+        let unknown = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n";
+        assert!(matches!(
+            parse_all(unknown),
+            Err(HttpError::NotImplemented(_))
+        ));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = Limits {
+            max_header_size: 64,
+            max_body_size: 4,
+        };
+        let long_head = format!("GET / HTTP/1.1\r\nHost: a\r\nX-Padding: {}", "a".repeat(64));
+        assert_eq!(
+            RequestParser::with_limits(limits).parse(long_head.as_bytes()),
+            Err(HttpError::HeadersTooLarge(64))
+        );
+        let empty_lines = "\r\n".repeat(40);
+        assert_eq!(
+            RequestParser::with_limits(limits).parse(empty_lines.as_bytes()),
+            Err(HttpError::HeadersTooLarge(64))
+        );
+        let big_body = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\n";
+        assert_eq!(
+            RequestParser::with_limits(limits).parse(big_body),
+            Err(HttpError::PayloadTooLarge(4))
+        );
+    }
+
+    #[test]
+    fn extracts_urlencoded_forms() {
+        let raw = b"POST /login HTTP/1.1\r\nHost: a\r\n\
+            Content-Type: Application/X-WWW-Form-Urlencoded; charset=UTF-8\r\n\
+            Content-Length: 27\r\n\r\nusername=mock1&pwd=p%40ss+1";
+        let (request, _) = parse_all(raw).unwrap().unwrap();
+        assert_eq!(
+            request.media_type().as_deref(),
+            Some("application/x-www-form-urlencoded")
+        );
+        let form = request.form().unwrap();
+        assert_eq!(form.get("username"), Some("mock1"));
+        assert_eq!(form.get("pwd"), Some("p@ss 1"));
+
+        let raw = b"POST /login HTTP/1.1\r\nHost: a\r\nContent-Type: application/json\r\n\
+            Content-Length: 2\r\n\r\n{}";
+        let (request, _) = parse_all(raw).unwrap().unwrap();
+        assert_eq!(
+            request.form(),
+            Err(HttpError::UnsupportedMediaType(
+                "application/json".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,304 @@
+#![forbid(unsafe_code)]
+
+use crate::chunked::{encode_chunk, encode_last_chunk};
+use crate::cookie::Cookie;
+use crate::headers::Headers;
+use crate::request::Version;
+use crate::route::TcpStreamType;
+use crate::status::StatusCode;
+use bytes::Bytes;
+use futures::stream::{BoxStream, Stream, StreamExt};
+use std::fmt;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+use tokio::io::Result as IoResult;
+
+/// A response body. Bodies are raw bytes all the way to the socket, so binary
+/// content such as images or archives is sent exactly as it is stored.
+pub enum Body {
+    Empty,
+    Bytes(Bytes),
+    /// Sent with chunked transfer-coding, for bodies whose length isn't known up front.
+    Stream(BoxStream<'static, Bytes>),
+    /// An open file that is copied to the client while the response is written, 'len' bytes
+    /// from its current position.
+    File {
+        file: tokio::fs::File,
+        len: u64,
+    },
+}
+
+impl Body {
+    pub fn stream<S>(chunks: S) -> Body
+    where
+        S: Stream<Item = Bytes> + Send + 'static,
+    {
+        Body::Stream(chunks.boxed())
+    }
+
+    /// Opens `path` so it can be sent as a body. Failing here, rather than while the
+    /// response is being written, lets the handler still pick a different response.
+    pub async fn file(path: &Path) -> IoResult<Body> {
+        let file = tokio::fs::File::open(path).await?;
+        let len = file.metadata().await?.len();
+        Ok(Body::File { file, len })
+    }
+
+    pub fn is_stream(&self) -> bool {
+        matches!(self, Body::Stream(_))
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Body::Empty"),
+            Body::Bytes(bytes) => write!(f, "Body::Bytes({} bytes)", bytes.len()),
+            Body::Stream(_) => write!(f, "Body::Stream"),
+            Body::File { len, .. } => write!(f, "Body::File({} bytes)", len),
+        }
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(Bytes::from(bytes))
+    }
+}
+
+impl From<&'static [u8]> for Body {
+    fn from(bytes: &'static [u8]) -> Body {
+        Body::Bytes(Bytes::from_static(bytes))
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Bytes(Bytes::from(text))
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Body {
+        Body::Bytes(Bytes::from_static(text.as_bytes()))
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Body,
+}
+
+impl Response {
+    /// A response with the given status and no body.
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Empty,
+        }
+    }
+
+    pub fn builder(status: StatusCode) -> ResponseBuilder {
+        ResponseBuilder {
+            response: Response::new(status),
+        }
+    }
+
+    pub fn html(status: StatusCode, content: impl Into<Body>) -> Response {
+        Response::builder(status)
+            .content_type("text/html; charset=UTF-8")
+            .body(content)
+    }
+
+    pub fn json(status: StatusCode, content: impl Into<Body>) -> Response {
+        Response::builder(status)
+            .content_type("application/json")
+            .body(content)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The status line and header fields, including the empty line that ends them.
+    /// We always answer as HTTP/1.1, which 1.0 clients are required to accept.
+    pub fn head_to_bytes(&self) -> Vec<u8> {
+        serialize_head(self.status, &self.headers)
+    }
+
+    /// Adds the framing headers that match the body and writes the response to the client.
+    /// With 'head_only' set (a HEAD request), the headers describe the body but it isn't sent.
+    pub async fn write_to(
+        self,
+        stream: &mut TcpStreamType,
+        version: Version,
+        head_only: bool,
+    ) -> IoResult<()> {
+        let Response {
+            status,
+            mut headers,
+            body,
+        } = self;
+        let send_body = status.allows_body() && !head_only;
+
+        match body {
+            _ if !status.allows_body() => {
+                headers.remove("Transfer-Encoding");
+                if status != StatusCode::NotModified {
+                    headers.remove("Content-Length");
+                }
+                stream.write_all(&serialize_head(status, &headers)).await?;
+            }
+            Body::Empty => {
+                headers.insert("Content-Length", "0");
+                stream.write_all(&serialize_head(status, &headers)).await?;
+            }
+            Body::Bytes(bytes) => {
+                headers.insert("Content-Length", bytes.len().to_string());
+                stream.write_all(&serialize_head(status, &headers)).await?;
+                if send_body {
+                    stream.write_all(&bytes).await?;
+                }
+            }
+            Body::File { file, len } => {
+                headers.insert("Content-Length", len.to_string());
+                stream.write_all(&serialize_head(status, &headers)).await?;
+                if send_body {
+                    // Never more than announced, even if the file grew in the meantime.
+                    let mut file = file.take(len);
+                    let mut chunk = vec![0; 16 * 1024];
+                    loop {
+                        let bytes_read = file.read(&mut chunk).await?;
+                        if bytes_read == 0 {
+                            break;
+                        }
+                        stream.write_all(&chunk[..bytes_read]).await?;
+                    }
+                }
+            }
+            Body::Stream(mut chunks) => {
+                headers.remove("Content-Length");
+                // HTTP/1.0 has no chunked coding, the body just ends when the connection does.
+                let chunked = version == Version::Http11;
+                if chunked {
+                    headers.insert("Transfer-Encoding", "chunked");
+                }
+                stream.write_all(&serialize_head(status, &headers)).await?;
+                if send_body {
+                    while let Some(chunk) = chunks.next().await {
+                        match chunked {
+                            true => stream.write_all(&encode_chunk(&chunk)).await?,
+                            false => stream.write_all(&chunk).await?,
+                        }
+                        stream.flush().await?;
+                    }
+                    if chunked {
+                        stream
+                            .write_all(&encode_last_chunk(&Headers::new()))
+                            .await?;
+                    }
+                }
+            }
+        }
+        stream.flush().await
+    }
+}
+
+fn serialize_head(status: StatusCode, headers: &Headers) -> Vec<u8> {
+    let mut head = format!("{} {}\r\n", Version::Http11, status);
+    for (name, value) in headers.iter() {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
+
+pub struct ResponseBuilder {
+    response: Response,
+}
+
+impl ResponseBuilder {
+    /// Adds a header field, keeping any existing fields with the same name.
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> ResponseBuilder {
+        self.response.headers.append(name, value);
+        self
+    }
+
+    /// Adds a `Set-Cookie` field, one per cookie.
+    pub fn cookie(mut self, cookie: &Cookie) -> ResponseBuilder {
+        self.response
+            .headers
+            .append("Set-Cookie", cookie.to_string());
+        self
+    }
+
+    pub fn content_type(mut self, value: &str) -> ResponseBuilder {
+        self.response.headers.insert("Content-Type", value);
+        self
+    }
+
+    /// Marks the response as not to be compressed (`Cache-Control: no-transform`). Use it for
+    /// responses that carry secrets such as tokens next to anything the client can influence.
+    pub fn no_compression(mut self) -> ResponseBuilder {
+        if !self
+            .response
+            .headers
+            .has_token("Cache-Control", "no-transform")
+        {
+            self.response
+                .headers
+                .append("Cache-Control", "no-transform");
+        }
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Body>) -> Response {
+        self.response.body = body.into();
+        self.response
+    }
+
+    pub fn empty(self) -> Response {
+        self.response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_head() {
+        let response = Response::builder(StatusCode::NotFound)
+            .content_type("text/plain")
+            .header("Set-Cookie", "a=1")
+            .header("Set-Cookie", "b=2")
+            .body("missing");
+        assert_eq!(
+            response.head_to_bytes(),
+            b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\n\
+            Set-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn strips_line_breaks_from_headers() {
+        let response = Response::builder(StatusCode::Found)
+            .header("Location", "/next\r\nSet-Cookie: admin=1")
+            .header("X-Evil\r\nX-Injected", "1")
+            .empty();
+        assert_eq!(
+            response.head_to_bytes(),
+            b"HTTP/1.1 302 Found\r\nLocation: /nextSet-Cookie: admin=1\r\n\
+            X-EvilX-Injected: 1\r\n\r\n"
+        );
+    }
+}
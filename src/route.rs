@@ -1,28 +1,40 @@
 #![forbid(unsafe_code)]
 
+use crate::asset_cache::AssetCache;
+use crate::audit::{self, LoginOutcome};
+use crate::compression::Compression;
+use crate::cors::{Cors, OriginPattern};
+use crate::csrf::{Csrf, CSRF_HEADER};
+use crate::error::{ConfigError, FormError, HttpError, PsqlError, SessionError};
+use crate::handler::Handler;
+use crate::middleware::{HandlerExt, Logger, SecurityHeaders};
 use crate::models::LoginPayload;
+use crate::multipart::{Multipart, MultipartConfig};
+use crate::password::PasswordConfig;
 use crate::psql::db_psql_validate_user;
-use crate::status; // Response status codes
-use futures_util::{SinkExt, StreamExt};
+use crate::rate_limit::LoginLimiter;
+use crate::request::{Limits, Method, Request, RequestParser, Version};
+use crate::response::Response;
+use crate::router::Router;
+use crate::session::{Session, SessionManager};
+use crate::state::AppState;
+use crate::static_files::StaticFiles;
+use crate::status::StatusCode;
+use bytes::Bytes;
 use once_cell::sync::Lazy;
 use sqlx::postgres::PgPool;
-use std::{env, fs};
+use std::net::SocketAddr;
 use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::fs;
 use tokio::io::Result as IoResult;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::time;
 
-pub enum Route {
-    Homepage,
-    Favicon,
-    BadRequest,
-    Login,
-}
-
 pub enum TcpStreamType {
-    TokioTls(tokio_rustls::server::TlsStream<tokio::net::TcpStream>), // for TLS
-    TokioNoTls(tokio::net::TcpStream),                                // No TLS
+    TokioTls(Box<tokio_rustls::server::TlsStream<tokio::net::TcpStream>>), // for TLS
+    TokioNoTls(tokio::net::TcpStream),                                     // No TLS
 }
 
 impl TcpStreamType {
@@ -33,11 +45,12 @@ impl TcpStreamType {
             TcpStreamType::TokioNoTls(no_tls_stream) => no_tls_stream.read(buf).await,
         }
     }
-    // Write
-    pub async fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+    // Write the whole buffer, however many calls that takes. A plain 'write' may stop
+    // short, which used to truncate responses, so it's deliberately not exposed.
+    pub async fn write_all(&mut self, buf: &[u8]) -> IoResult<()> {
         match self {
-            TcpStreamType::TokioTls(tls_stream) => tls_stream.write(buf).await,
-            TcpStreamType::TokioNoTls(no_tls_stream) => no_tls_stream.write(buf).await,
+            TcpStreamType::TokioTls(tls_stream) => tls_stream.write_all(buf).await,
+            TcpStreamType::TokioNoTls(no_tls_stream) => no_tls_stream.write_all(buf).await,
         }
     }
     // Flush
@@ -47,218 +60,626 @@ impl TcpStreamType {
             TcpStreamType::TokioNoTls(no_tls_stream) => no_tls_stream.flush().await,
         }
     }
+    // The client's address, the TCP peer under the TLS layer
+    pub fn peer_addr(&self) -> IoResult<SocketAddr> {
+        match self {
+            TcpStreamType::TokioTls(tls_stream) => tls_stream.get_ref().0.peer_addr(),
+            TcpStreamType::TokioNoTls(no_tls_stream) => no_tls_stream.peer_addr(),
+        }
+    }
+    // Shutdown, which also sends the TLS close_notify alert
+    pub async fn shutdown(&mut self) -> IoResult<()> {
+        match self {
+            TcpStreamType::TokioTls(tls_stream) => tls_stream.shutdown().await,
+            TcpStreamType::TokioNoTls(no_tls_stream) => no_tls_stream.shutdown().await,
+        }
+    }
 }
 
 // HTML files
-static PATH_TO_HOME: Lazy<&Path> = Lazy::new(|| Path::new("resources/html/home.html"));
+static PATH_TO_401: Lazy<&Path> = Lazy::new(|| Path::new("resources/html/401.html"));
 static PATH_TO_404: Lazy<&Path> = Lazy::new(|| Path::new("resources/html/404.html"));
-static PATH_TO_FAVICON: Lazy<&Path> = Lazy::new(|| Path::new("resources/html/favicon.ico"));
-// Requests
-static REQUEST_GET_HOME: &[u8; 16] = b"GET / HTTP/1.1\r\n";
-static REQUEST_GET_FAVICON: &[u8; 27] = b"GET /favicon.ico HTTP/1.1\r\n";
-static REQUEST_POST_LOGIN: &[u8; 22] = b"POST /login HTTP/1.1\r\n";
-
-// If adding/removing headers, make sure the last header doesn't terminate in \r\n
-// because that is already being added to the response string in fn 'build_http_response'
-fn build_http_headers(security_enabled: bool, payload_length: usize, content_type: &str) -> String {
-    let headers: String = match security_enabled {
-        true => {
-            format!(
-                "Connection: keep-alive\r\n\
-                Content-Type: {}\r\n\
-                Access-Control-Allow-Origin: *\r\n\
-                X-Content-Type-Options: nosniff\r\n\
-                X-XSS-Protection: 1; mode=block\r\n\
-                Content-Security-Policy: default-src 'self'\r\n\
-                Content-Length: {}",
-                content_type, payload_length
-            )
-        }
-        false => {
-            format!(
-                "Connection: keep-alive\r\n\
-                Content-Type: {}\r\n\
-                Content-Length: {}",
-                content_type, payload_length
-            )
+
+fn error_response(err: &HttpError) -> Response {
+    let status = match err {
+        HttpError::BadRequest(_) => StatusCode::BadRequest,
+        HttpError::VersionNotSupported(_) => StatusCode::HttpVersionNotSupported,
+        HttpError::NotImplemented(_) => StatusCode::NotImplemented,
+        HttpError::HeadersTooLarge(_) => StatusCode::RequestHeaderFieldsTooLarge,
+        HttpError::PayloadTooLarge(_) => StatusCode::PayloadTooLarge,
+        HttpError::UnsupportedMediaType(_) => StatusCode::UnsupportedMediaType,
+    };
+    Response::new(status)
+}
+
+// Small pages and icons served by the default routes, kept in memory between requests.
+static ASSETS: Lazy<AssetCache> = Lazy::new(AssetCache::new);
+
+// Reads an HTML page through the asset cache, answering with a 500 if that fails so the
+// client still gets a response on a persistent connection.
+async fn html_page_response(status: StatusCode, path: &Path) -> Response {
+    let contents = match ASSETS.get(path).await {
+        Ok(Some(asset)) => Ok(asset.content),
+        Ok(None) => fs::read(path).await.map(Bytes::from),
+        Err(e) => Err(e),
+    };
+    match contents {
+        Ok(contents) => Response::html(status, contents),
+        Err(e) => {
+            eprintln!("Error reading file {}: {}", path.display(), e);
+            Response::new(StatusCode::InternalServerError)
         }
+    }
+}
+
+// Login responses will carry credentials and session state, so they are never compressed.
+fn login_success_response() -> Response {
+    Response::builder(StatusCode::Ok)
+        .content_type("application/json")
+        .no_compression()
+        .body(r#"{"success":true}"#)
+}
+
+async fn write_to_http_client(
+    stream: &mut TcpStreamType,
+    mut response: Response,
+    version: Version,
+    head_only: bool,
+    keep_alive: bool,
+) {
+    let connection = match keep_alive {
+        true => "keep-alive",
+        false => "close",
+    };
+    response.headers.insert("Connection", connection);
+
+    if let Err(e) = response.write_to(stream, version, head_only).await {
+        eprintln!("Error writing to stream: {}", e);
+    }
+}
+
+// Reads the username and password however the client sent them: in a JSON, urlencoded
+// (login.html) or multipart body. Never from the query string, where they would end up
+// in logs and history, and where a plain link could log someone in.
+async fn login_credentials(request: &Request) -> Result<(String, String), Response> {
+    let missing = || {
+        eprintln!("Missing login credentials");
+        Response::new(StatusCode::BadRequest)
     };
-    headers
+    match request.media_type().as_deref() {
+        Some("application/json") => match serde_json::from_slice::<LoginPayload>(&request.body) {
+            Ok(payload) => Ok((payload.username.to_string(), payload.pwd.to_string())),
+            Err(_) => {
+                eprintln!("Failed to parse JSON payload");
+                Err(Response::new(StatusCode::BadRequest))
+            }
+        },
+        Some("application/x-www-form-urlencoded") => {
+            let form = request.form().map_err(|e| error_response(&e))?;
+            match (form.get("username"), form.get("pwd")) {
+                (Some(username), Some(pwd)) => Ok((username.to_string(), pwd.to_string())),
+                _ => Err(missing()),
+            }
+        }
+        Some("multipart/form-data") => {
+            // Credentials only, there is no reason to accept files here.
+            let config = MultipartConfig {
+                max_files: 0,
+                ..MultipartConfig::default()
+            };
+            let form = match Multipart::from_request(request, &config).await {
+                Ok(form) => form,
+                Err(FormError::Http(e)) => {
+                    eprintln!("{}", e);
+                    return Err(error_response(&e));
+                }
+                Err(FormError::Io(e)) => {
+                    eprintln!("{}", e);
+                    return Err(Response::new(StatusCode::InternalServerError));
+                }
+            };
+            match (form.field("username"), form.field("pwd")) {
+                (Some(username), Some(pwd)) => Ok((username.to_string(), pwd.to_string())),
+                _ => Err(missing()),
+            }
+        }
+        other => {
+            eprintln!("Unsupported login content type: {:?}", other);
+            Err(Response::new(StatusCode::UnsupportedMediaType))
+        }
+    }
+}
+
+// Without a pool there is nobody to ask, which the client should see as a temporary
+// outage rather than a failed login.
+fn database_unavailable() -> Response {
+    Response::builder(StatusCode::ServiceUnavailable)
+        .header("Retry-After", "5")
+        .empty()
 }
 
-fn build_http_response<T: AsRef<[u8]>>(status: &str, payload: T, content_type: &str) -> String {
-    let payload_bytes = payload.as_ref();
-    let headers = build_http_headers(true, payload_bytes.len(), content_type);
-    let response_payload = match payload_bytes {
-        // If it's a UTF-8 string, format it as is.
-        // This will work for String and &str types.
-        bytes if std::str::from_utf8(bytes).is_ok() => {
-            std::str::from_utf8(bytes).unwrap().to_string()
+// Too many attempts, from the client's address or for the username.
+fn too_many_requests(retry_after: Duration) -> Response {
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    Response::builder(StatusCode::TooManyRequests)
+        .header("Retry-After", secs.max(1).to_string())
+        .empty()
+}
+
+async fn login(request: Request) -> Response {
+    let limiter = request.state::<LoginLimiter>();
+    if let (Some(limiter), Some(peer_addr)) = (limiter, request.peer_addr) {
+        if let Some(retry_after) = limiter.check_ip(peer_addr.ip()) {
+            eprintln!("Too many login attempts from {}", peer_addr.ip());
+            return too_many_requests(retry_after);
+        }
+    }
+    let (username, pwd) = match login_credentials(&request).await {
+        Ok(credentials) => credentials,
+        Err(response) => return response,
+    };
+    let user = match LoginPayload::new(&username, &pwd) {
+        Ok(user) => user,
+        Err(_) => {
+            eprintln!("Failed to create a new user");
+            return Response::new(StatusCode::InternalServerError);
+        }
+    };
+    let Some(pool) = request.state::<PgPool>() else {
+        eprintln!("Login attempted but no database is configured, set 'DATABASE_URL'");
+        return database_unavailable();
+    };
+    if let Some(limiter) = limiter {
+        match limiter.check_user(&username).await {
+            Ok(None) => {}
+            Ok(Some(retry_after)) => {
+                audit::login(user.username, LoginOutcome::LockedOut);
+                return too_many_requests(retry_after);
+            }
+            Err(err) if err.is_unavailable() => {
+                eprintln!("Database unavailable: {}", err);
+                return database_unavailable();
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                return Response::new(StatusCode::InternalServerError);
+            }
+        }
+    }
+
+    let config = request
+        .state::<PasswordConfig>()
+        .copied()
+        .unwrap_or_default();
+    // Unknown users and wrong passwords get the very same 401, only the audit trail
+    // knows which one it was.
+    let outcome = match db_psql_validate_user(pool, &user, &config).await {
+        Ok(_) => LoginOutcome::Success,
+        Err(PsqlError::SqlxError(sqlx::Error::RowNotFound)) => LoginOutcome::UnknownUser,
+        Err(PsqlError::PasswordMismatch) => LoginOutcome::WrongPassword,
+        Err(err) if err.is_unavailable() => {
+            eprintln!("Database unavailable: {}", err);
+            return database_unavailable();
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            return Response::new(StatusCode::InternalServerError);
         }
-        // If it's not a UTF-8 string (i.e., arbitrary binary data), format the bytes directly.
-        // This will work for Vec<u8> and &[u8] types.
-        bytes => format!("{:?}", bytes),
     };
-    format!("{}\r\n{}\r\n\r\n{}", status, headers, response_payload)
+    audit::login(user.username, outcome);
+    if let Some(limiter) = limiter {
+        let recorded = match outcome {
+            LoginOutcome::Success => limiter.record_success(user.username).await,
+            _ => match limiter.record_failure(user.username).await {
+                Ok(Some(lockout)) => {
+                    audit::lockout(user.username, lockout);
+                    Ok(())
+                }
+                Ok(None) => Ok(()),
+                Err(err) => Err(err),
+            },
+        };
+        if let Err(err) = recorded {
+            eprintln!("Failed to record the login attempt: {}", err);
+        }
+    }
+    match outcome {
+        LoginOutcome::Success => start_session(&request, user.username).await,
+        _ => html_page_response(StatusCode::Unauthorized, *PATH_TO_401).await,
+    }
 }
 
-async fn write_404_to_http_client(stream: &mut TcpStreamType) {
-    if let Ok(contents) = fs::read_to_string(*PATH_TO_404) {
-        let response =
-            build_http_response(status::STATUS_404, contents, "text/html; charset=UTF-8");
-        write_to_http_client(stream, response).await;
-    } else {
-        eprintln!("Error reading file: 404.html");
+// A successful login always gets a brand new session, see `SessionManager::login`.
+async fn start_session(request: &Request, username: &str) -> Response {
+    let Some(sessions) = request.state::<SessionManager>() else {
+        return login_success_response();
+    };
+    match sessions.login(request, username).await {
+        Ok((_, cookie)) => {
+            let mut response = login_success_response();
+            response.headers.append("Set-Cookie", cookie.to_string());
+            response
+        }
+        Err(err) => session_error_response(&err),
     }
 }
 
-async fn write_to_http_client(stream: &mut TcpStreamType, response: String) {
-    if let Err(e) = stream.write(response.as_bytes()).await {
-        eprintln!("Error writing to stream: {}", e);
+fn session_error_response(err: &SessionError) -> Response {
+    eprintln!("{}", err);
+    match err.is_unavailable() {
+        true => database_unavailable(),
+        false => Response::new(StatusCode::InternalServerError),
     }
-    if let Err(e) = stream.flush().await {
-        eprintln!("Error flushing stream: {}", e);
+}
+
+async fn logout(request: Request) -> Response {
+    let Some(sessions) = request.state::<SessionManager>() else {
+        return Response::new(StatusCode::NoContent);
+    };
+    match sessions.logout(&request).await {
+        Ok(cookie) => Response::builder(StatusCode::NoContent)
+            .cookie(&cookie)
+            .empty(),
+        Err(err) => session_error_response(&err),
     }
 }
 
-async fn process_request_async(stream: &mut TcpStreamType, buffer: &[u8], bytes_read: usize) {
-    let mut route: Route = if buffer.starts_with(REQUEST_GET_HOME) {
-        Route::Homepage
-    } else if buffer.starts_with(REQUEST_GET_FAVICON) {
-        Route::Favicon
-    } else if buffer.starts_with(REQUEST_POST_LOGIN) {
-        Route::Login
-    } else {
-        Route::BadRequest
+// Who the session cookie belongs to, mostly so clients can check they are logged in.
+async fn whoami(request: Request) -> Response {
+    let Some(sessions) = request.state::<SessionManager>() else {
+        return Response::new(StatusCode::Unauthorized);
     };
-    //let http_request1 = String::from_utf8(buffer.to_vec());
-    let http_request = String::from_utf8_lossy(&buffer[..bytes_read]);
-    // http_request_split[0] = path + headers
-    // http_request_split[1] = payload (if any, a GET for instance doesn't contain any payload)
-    let http_request_split: Vec<&str> = http_request.split("\r\n\r\n").collect();
-
-    if http_request_split.len() < 2 {
-        eprintln!("Invalid HTTP request format.");
-        route = Route::BadRequest;
+    match sessions.load(&request).await {
+        Ok(Some(Session {
+            username: Some(username),
+            ..
+        })) => Response::builder(StatusCode::Ok)
+            .content_type("application/json")
+            .no_compression()
+            .body(serde_json::json!({ "username": username }).to_string()),
+        Ok(_) => Response::new(StatusCode::Unauthorized),
+        Err(err) => session_error_response(&err),
     }
+}
 
-    match route {
-        Route::BadRequest => {
-            write_404_to_http_client(stream).await;
-        }
-        Route::Homepage => {
-            if let Ok(contents) = fs::read_to_string(*PATH_TO_HOME) {
-                let response =
-                    build_http_response(status::STATUS_200, contents, "text/html; charset=UTF-8");
-                write_to_http_client(stream, response).await;
-            } else {
-                eprintln!("Error reading file: home.html");
+async fn not_found(_request: Request) -> Response {
+    html_page_response(StatusCode::NotFound, *PATH_TO_404).await
+}
+
+/// The routes served by the ironclad server itself. Login uses the `PgPool` in the
+/// server's state, and answers with 503 without one or while the database is down.
+/// With a `SessionManager` in the state it also starts a session, which '/whoami'
+/// reports on and '/logout' ends.
+pub fn default_router() -> Router {
+    let pages = StaticFiles::new("resources/html")
+        .cache_control("*.ico", "public, max-age=86400")
+        .precompressed(true)
+        .cache(ASSETS.clone());
+    Router::new()
+        .get("/", pages.clone().index_file(Some("home.html")))
+        .get("/favicon.ico", pages.clone())
+        .get("/static/*path", pages)
+        .post("/login", login)
+        .post("/logout", logout)
+        .get("/whoami", whoami)
+        .fallback(not_found)
+}
+
+/// The default routes wrapped in the default middleware: CSRF protection, compression,
+/// CORS, security headers and request logging. 'secure' is whether the server runs
+/// over TLS, which decides if the CSRF cookie can be HTTPS only. 'origins' are the
+/// other origins allowed to call the server with cookies, exactly or as patterns.
+/// Fails on anything that isn't an origin, and on '*', which would let every site
+/// make requests with the user's cookies.
+pub fn default_handler(secure: bool, origins: &[String]) -> Result<impl Handler, ConfigError> {
+    let mut csrf = Csrf::new().secure(secure);
+    let mut cors = Cors::new()
+        .allow_headers(&["Content-Type", CSRF_HEADER])
+        .allow_credentials(true)
+        .max_age(Duration::from_secs(600));
+    for origin in origins {
+        match OriginPattern::parse(origin) {
+            Ok(OriginPattern::Any) => {
+                return Err(ConfigError::ParseError(
+                    "'*' can't be an allowed origin, cookies are sent cross-origin".to_string(),
+                ))
             }
+            Ok(_) => {}
+            Err(err) => return Err(ConfigError::ParseError(err)),
         }
-        Route::Favicon => {
-            if let Ok(contents) = fs::read(*PATH_TO_FAVICON) {
-                let response = build_http_response(status::STATUS_200, contents, "image/x-icon");
-                write_to_http_client(stream, response).await;
-            } else {
-                eprintln!("Error reading file: favicon.ico");
+        csrf = csrf.trusted_origin(origin);
+        cors = cors.allow_origin(origin);
+    }
+    Ok(default_router()
+        .layer(csrf)
+        .layer(Compression::new())
+        .layer(cors)
+        .layer(SecurityHeaders::default())
+        .layer(Logger))
+}
+
+/// Serves requests on a connection until the client asks to close it, goes quiet for
+/// longer than 'keep_alive_timeout', or sends something we can't parse. A request head
+/// also has to be complete within 'keep_alive_timeout' of its first byte, however
+/// steadily it trickles in. Requests that were pipelined behind the current one are
+/// already in the buffer and are answered in order before reading again. Multipart
+/// uploads are written to disk while they are read if there is a `MultipartConfig` in
+/// the state.
+pub async fn handle_connection_async(
+    stream: &mut TcpStreamType,
+    limits: Limits,
+    keep_alive_timeout: Duration,
+    handler: Arc<dyn Handler>,
+    state: AppState,
+) {
+    let mut parser = RequestParser::with_limits(limits);
+    if let Some(uploads) = state.get::<MultipartConfig>() {
+        parser = parser.uploads(uploads.clone());
+    }
+    let mut buffer: Vec<u8> = Vec::with_capacity(1024);
+    let mut chunk = [0; 4096];
+    let peer_addr = stream.peer_addr().ok();
+    // Each read gets the timeout on its own, a head sent a byte at a time would never
+    // hit it. So the whole head gets one deadline too (slowloris).
+    let mut head_deadline: Option<time::Instant> = None;
+
+    loop {
+        let request = match parser.parse(&buffer) {
+            Ok(Some((mut request, consumed))) => {
+                buffer.drain(..consumed);
+                head_deadline = None;
+                request.state = state.clone();
+                request.peer_addr = peer_addr;
+                request
             }
-        }
-        Route::Login => {
-            dotenv::dotenv().ok();
-            let database_url =
-                env::var("DATABASE_URL").expect("Failed to load 'DATABASE_URL' env variable");
-
-            let http_payload = http_request_split[1];
-            //println!("JSON sent by client: {}", http_payload);
-
-            if let Ok(login_payload) = serde_json::from_str::<LoginPayload>(http_payload) {
-                //println!("user:{}", &login_payload.username);
-                if let Ok(user) = LoginPayload::new(login_payload.username, login_payload.pwd) {
-                    if let Ok(pool) = PgPool::connect(database_url.as_str()).await {
-                        match db_psql_validate_user(&pool, &user).await {
-                            Ok(_) => {
-                                write_to_client(
-                                    stream,
-                                    build_http_response_login(status::STATUS_200),
-                                )
-                                .await;
-                            }
-                            Err(PsqlError::SqlxError(sqlx::Error::RowNotFound)) => {
-                                println!("User '{}' does not exist in database.", user.username);
-                                if let Ok(contents) = fs::read_to_string(*PATH_TO_401) {
-                                    write_to_client(
-                                        stream,
-                                        build_http_response(
-                                            status::STATUS_401,
-                                            contents,
-                                            "text/html; charset=UTF-8",
-                                        ),
-                                    )
-                                    .await;
-                                } else {
-                                    eprintln!("Error reading file: 401.html");
-                                }
-                            }
-                            Err(PsqlError::PasswordMismatch) => {
-                                println!(
-                                    "{} for user '{}'.",
-                                    PsqlError::PasswordMismatch,
-                                    user.username
-                                );
-                                if let Ok(contents) = fs::read_to_string(*PATH_TO_401) {
-                                    write_to_client(
-                                        stream,
-                                        build_http_response(
-                                            status::STATUS_401,
-                                            contents,
-                                            "text/html; charset=UTF-8",
-                                        ),
-                                    )
-                                    .await;
-                                } else {
-                                    eprintln!("Error reading file: 401.html");
-                                }
-                            }
-                            Err(PsqlError::SqlxError(err)) => {
-                                eprintln!("{}", err);
-                                write_to_client(
-                                    stream,
-                                    build_http_response(
-                                        status::STATUS_500,
-                                        "",
-                                        "text/html; charset=UTF-8",
-                                    ),
-                                )
-                                .await;
-                            }
+            Ok(None) => {
+                match parser.stream_body(&mut buffer).await {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        let response = match e {
+                            FormError::Http(e) => error_response(&e),
+                            FormError::Io(_) => Response::new(StatusCode::InternalServerError),
+                        };
+                        write_to_http_client(stream, response, Version::Http11, false, false).await;
+                        break;
+                    }
+                }
+                let idle_deadline = time::Instant::now() + keep_alive_timeout;
+                let deadline = match parser.reading_head() && !buffer.is_empty() {
+                    true => *head_deadline.get_or_insert(idle_deadline),
+                    false => idle_deadline,
+                };
+                match time::timeout_at(deadline, stream.read(&mut chunk)).await {
+                    Ok(Ok(0)) => {
+                        if !buffer.is_empty() {
+                            eprintln!("Invalid HTTP request format.");
                         }
+                        return;
+                    }
+                    Ok(Ok(bytes_read)) => buffer.extend_from_slice(&chunk[..bytes_read]),
+                    Ok(Err(e)) => {
+                        eprintln!("Error reading from stream: {}", e);
+                        return;
+                    }
+                    Err(_) if buffer.is_empty() => break,
+                    Err(_) => {
+                        eprintln!("Timed out waiting for the rest of the request");
+                        let response = Response::new(StatusCode::RequestTimeout);
+                        write_to_http_client(stream, response, Version::Http11, false, false).await;
+                        break;
                     }
-                } else {
-                    eprintln!("Failed to create a new user");
                 }
-            } else {
-                eprintln!("Failed to parse JSON payload");
+                continue;
             }
+            Err(e) => {
+                // The stream can't be trusted to be in sync after a malformed request, so close it.
+                eprintln!("{}", e);
+                let response = error_response(&e);
+                write_to_http_client(stream, response, Version::Http11, false, false).await;
+                break;
+            }
+        };
+
+        let version = request.version;
+        let head_only = request.method == Method::Head;
+        let keep_alive_requested = request.keep_alive();
+        let response = handler.call(request).await;
+        // Without chunked coding, an HTTP/1.0 streamed body can only end by closing.
+        let keep_alive = keep_alive_requested
+            && !response.headers.has_token("Connection", "close")
+            && !(version == Version::Http10 && response.body.is_stream());
+        write_to_http_client(stream, response, version, head_only, keep_alive).await;
+        if !keep_alive {
+            break;
         }
     }
+
+    if let Err(e) = stream.shutdown().await {
+        eprintln!("Error shutting down stream: {}", e);
+    }
 }
 
-pub async fn handle_connection_async(stream: &mut TcpStreamType) {
-    let mut buffer = [0; 1024];
-    match stream.read(&mut buffer).await {
-        Ok(bytes_read) => {
-            if bytes_read == 0 {
-                println!("Connection closed");
-                return;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::Body;
+    use crate::session::{MemoryStore, SessionConfig};
+    use crate::test_support::TempDir;
+    use std::env;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn serves_binary_files_byte_for_byte() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut stream = TcpStreamType::TokioNoTls(socket);
+            let handler: Arc<dyn Handler> = Arc::new(default_router());
+            handle_connection_async(
+                &mut stream,
+                Limits::default(),
+                Duration::from_secs(1),
+                handler,
+                AppState::default(),
+            )
+            .await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /favicon.ico HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+
+        let favicon = std::fs::read("resources/html/favicon.ico").unwrap();
+        let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&response[..head_end]);
+        assert!(head.contains(&format!("Content-Length: {}\r\n", favicon.len())));
+        assert_eq!(&response[head_end..], &favicon[..]);
+    }
+
+    #[tokio::test]
+    async fn times_out_heads_sent_a_byte_at_a_time() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let handler: Arc<dyn Handler> = Arc::new(default_router());
+            handle_connection_async(
+                &mut TcpStreamType::TokioNoTls(socket),
+                Limits::default(),
+                Duration::from_millis(300),
+                handler,
+                AppState::default(),
+            )
+            .await;
+        });
+
+        // Every byte comes well within the read timeout, the head as a whole doesn't.
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let started = time::Instant::now();
+        let mut response = Vec::new();
+        let mut chunk = [0; 1024];
+        for byte in format!("GET / HTTP/1.1\r\nX-Padding: {}", "a".repeat(64)).bytes() {
+            if client.write_all(&[byte]).await.is_err() {
+                break;
             }
-            let request_data =
-                std::str::from_utf8(&buffer[0..bytes_read]).unwrap_or("<Invalid UTF-8>");
-            println!("Received request: \r\n{}", request_data);
+            match time::timeout(Duration::from_millis(100), client.read(&mut chunk)).await {
+                Ok(Ok(read)) => {
+                    response.extend_from_slice(&chunk[..read]);
+                    break;
+                }
+                Ok(Err(_)) => break,
+                Err(_) => {}
+            }
+        }
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 408 "));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
 
-            process_request_async(stream, &buffer, bytes_read).await;
+    #[tokio::test]
+    async fn rejects_multipart_bodies_over_the_body_limit() {
+        let dir = TempDir::new("uploads");
+        let mut with_uploads = AppState::new();
+        with_uploads.insert(MultipartConfig {
+            upload_dir: dir.to_path_buf(),
+            ..MultipartConfig::default()
+        });
+
+        let size = 2 * Limits::default().max_body_size;
+        let head = format!(
+            "POST /nowhere HTTP/1.1\r\nHost: test\r\n\
+            Content-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {}\r\n\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"big.bin\"\r\n\r\n",
+            size
+        );
+        for state in [AppState::default(), with_uploads] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (socket, _) = listener.accept().await.unwrap();
+                let handler: Arc<dyn Handler> = Arc::new(default_router());
+                handle_connection_async(
+                    &mut TcpStreamType::TokioNoTls(socket),
+                    Limits::default(),
+                    Duration::from_secs(5),
+                    handler,
+                    state,
+                )
+                .await;
+            });
+
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(head.as_bytes()).await.unwrap();
+            // The server may answer and close before all of this is sent.
+            let _ = client.write_all(&vec![b'x'; 64 * 1024]).await;
+            let mut response = Vec::new();
+            let _ = client.read_to_end(&mut response).await;
+            assert!(response.starts_with(b"HTTP/1.1 413 "));
         }
-        Err(e) => {
-            eprintln!("Error reading from stream: {}", e);
+        assert_eq!(std::fs::read_dir(&*dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn login_ignores_get_requests() {
+        let mut state = AppState::new();
+        state.insert(SessionManager::new(
+            MemoryStore::new(),
+            SessionConfig::default(),
+        ));
+        let router = default_router();
+
+        let raw = b"GET /login?username=mock1&pwd=password1 HTTP/1.1\r\nHost: test\r\n\r\n";
+        let mut request = RequestParser::new().parse(raw).unwrap().unwrap().0;
+        request.state = state;
+        let response = router.handle(request).await;
+        assert_eq!(response.status, StatusCode::MethodNotAllowed);
+        assert_eq!(response.header("Set-Cookie"), None);
+    }
+
+    #[tokio::test]
+    async fn login_failures_are_indistinguishable() {
+        dotenv::dotenv().ok();
+        let database_url =
+            env::var("DATABASE_URL").expect("Failed to read test 'database_url' env variable.");
+        let mock_username = env::var("DB_TEST_MOCK_USER_USERNAME")
+            .expect("Failed to read test 'mock username' env variable.");
+        let mut state = AppState::new();
+        state.insert(PgPool::connect(&database_url).await.unwrap());
+        let router = default_router();
+
+        let mut responses = Vec::new();
+        for username in [mock_username.as_str(), "no-such-user"] {
+            let body = format!("username={}&pwd=wrong", username);
+            let raw = format!(
+                "POST /login HTTP/1.1\r\nHost: test\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let mut request = RequestParser::new()
+                .parse(raw.as_bytes())
+                .unwrap()
+                .unwrap()
+                .0;
+            request.state = state.clone();
+            let response = router.handle(request).await;
+            let headers: Vec<(String, String)> = response
+                .headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            let Body::Bytes(body) = response.body else {
+                panic!("expected an in-memory body");
+            };
+            responses.push((response.status, headers, body));
         }
+        assert_eq!(responses[0].0, StatusCode::Unauthorized);
+        assert_eq!(responses[0], responses[1]);
     }
 }
@@ -0,0 +1,346 @@
+#![forbid(unsafe_code)]
+
+use crate::handler::Handler;
+use crate::query::decode_path_segment;
+use crate::request::{Method, Request};
+use crate::response::Response;
+use crate::status::StatusCode;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+
+/// Values captured from the request path by `:name` and `*name` segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathParams {
+    values: HashMap<String, String>,
+}
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|value| value.as_str())
+    }
+
+    pub fn insert(&mut self, name: &str, value: impl Into<String>) {
+        self.values.insert(name.to_string(), value.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+// Segment kinds are ordered by how specific they are, so '/users/me' wins over
+// '/users/:id', which wins over '/users/*rest'.
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+impl Segment {
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Literal(_) => 0,
+            Segment::Param(_) => 1,
+            Segment::Wildcard(_) => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PathPattern {
+    segments: Vec<Segment>,
+}
+
+impl PathPattern {
+    /// Parses patterns such as '/', '/users/:id' or '/static/*path'.
+    /// A wildcard matches the rest of the path and must be the last segment.
+    fn parse(pattern: &str) -> PathPattern {
+        assert!(
+            pattern.starts_with('/'),
+            "route pattern '{}' must start with '/'",
+            pattern
+        );
+        let segments: Vec<Segment> = split_path(pattern)
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Wildcard(name.to_string())
+                } else {
+                    Segment::Literal(segment.to_string())
+                }
+            })
+            .collect();
+        if let Some(position) = segments
+            .iter()
+            .position(|segment| matches!(segment, Segment::Wildcard(_)))
+        {
+            assert!(
+                position == segments.len() - 1,
+                "wildcard must be the last segment of route pattern '{}'",
+                pattern
+            );
+        }
+        PathPattern { segments }
+    }
+
+    /// Matches against the raw request path. Segments are percent-decoded one at a time,
+    /// so an encoded '/' ('%2F') never splits a segment in two.
+    fn matches(&self, path: &str) -> Option<PathParams> {
+        let mut params = PathParams::default();
+        let mut parts = split_path(path).map(decode_path_segment);
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Wildcard(name) => {
+                    let rest: Option<Vec<String>> = parts.by_ref().collect();
+                    params.insert(name, rest?.join("/"));
+                    return Some(params);
+                }
+                Segment::Literal(literal) => {
+                    if parts.next()?? != *literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let part = parts.next()??;
+                    if part.is_empty() {
+                        return None;
+                    }
+                    params.insert(name, part);
+                }
+            }
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+
+    fn ranks(&self) -> Vec<u8> {
+        self.segments.iter().map(Segment::rank).collect()
+    }
+}
+
+// '/' has no segments, and a trailing slash is ignored so '/login/' and '/login' are the same.
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    let trimmed = path.strip_prefix('/').unwrap_or(path);
+    let trimmed = trimmed.strip_suffix('/').unwrap_or(trimmed);
+    trimmed.split('/').filter(move |_| !trimmed.is_empty())
+}
+
+struct RouteEntry {
+    method: Method,
+    pattern: PathPattern,
+    handler: Box<dyn Handler>,
+}
+
+/// Dispatches requests to handlers registered per method and path pattern.
+///
+/// ```ignore
+/// let router = Router::new()
+///     .get("/", home)
+///     .get("/users/:id", show_user)
+///     .post("/login", login);
+/// ```
+///
+/// When the path matches but the method doesn't, the router answers 405 with an `Allow`
+/// header. HEAD falls back to the GET handler (the body is dropped when writing the
+/// response) and OPTIONS is answered with the allowed methods unless registered explicitly.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<RouteEntry>,
+    fallback: Option<Box<dyn Handler>>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    pub fn route(mut self, method: Method, path: &str, handler: impl Handler) -> Router {
+        self.routes.push(RouteEntry {
+            method,
+            pattern: PathPattern::parse(path),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get(self, path: &str, handler: impl Handler) -> Router {
+        self.route(Method::Get, path, handler)
+    }
+
+    pub fn post(self, path: &str, handler: impl Handler) -> Router {
+        self.route(Method::Post, path, handler)
+    }
+
+    pub fn put(self, path: &str, handler: impl Handler) -> Router {
+        self.route(Method::Put, path, handler)
+    }
+
+    pub fn patch(self, path: &str, handler: impl Handler) -> Router {
+        self.route(Method::Patch, path, handler)
+    }
+
+    pub fn delete(self, path: &str, handler: impl Handler) -> Router {
+        self.route(Method::Delete, path, handler)
+    }
+
+    /// Handler for requests that match no route. Defaults to an empty 404.
+    pub fn fallback(mut self, handler: impl Handler) -> Router {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    pub async fn handle(&self, mut request: Request) -> Response {
+        if request.method == Method::Options && request.target == "*" {
+            return allow_response(StatusCode::NoContent, self.all_methods());
+        }
+
+        let matching: Vec<(&RouteEntry, PathParams)> = self
+            .routes
+            .iter()
+            .filter_map(|route| {
+                route
+                    .pattern
+                    .matches(request.path())
+                    .map(|params| (route, params))
+            })
+            .collect();
+        if matching.is_empty() {
+            return match &self.fallback {
+                Some(fallback) => fallback.call(request).await,
+                None => Response::new(StatusCode::NotFound),
+            };
+        }
+
+        let found = most_specific(&matching, &request.method).or_else(|| match request.method {
+            Method::Head => most_specific(&matching, &Method::Get),
+            _ => None,
+        });
+        if let Some((route, params)) = found {
+            request.params = params.clone();
+            return route.handler.call(request).await;
+        }
+
+        let allowed = allowed_methods(matching.iter().map(|(route, _)| &route.method));
+        match request.method {
+            Method::Options => allow_response(StatusCode::NoContent, allowed),
+            _ => allow_response(StatusCode::MethodNotAllowed, allowed),
+        }
+    }
+
+    fn all_methods(&self) -> Vec<Method> {
+        allowed_methods(self.routes.iter().map(|route| &route.method))
+    }
+}
+
+impl Handler for Router {
+    fn call(&self, request: Request) -> BoxFuture<'_, Response> {
+        Box::pin(self.handle(request))
+    }
+}
+
+fn most_specific<'a, 'r>(
+    matching: &'a [(&'r RouteEntry, PathParams)],
+    method: &Method,
+) -> Option<&'a (&'r RouteEntry, PathParams)> {
+    matching
+        .iter()
+        .filter(|(route, _)| route.method == *method)
+        .min_by_key(|(route, _)| route.pattern.ranks())
+}
+
+// GET implies HEAD, and OPTIONS is always answered.
+fn allowed_methods<'a>(methods: impl Iterator<Item = &'a Method>) -> Vec<Method> {
+    let mut allowed: Vec<Method> = Vec::new();
+    for method in methods {
+        if !allowed.contains(method) {
+            allowed.push(method.clone());
+        }
+        if *method == Method::Get && !allowed.contains(&Method::Head) {
+            allowed.push(Method::Head);
+        }
+    }
+    if !allowed.contains(&Method::Options) {
+        allowed.push(Method::Options);
+    }
+    allowed
+}
+
+fn allow_response(status: StatusCode, allowed: Vec<Method>) -> Response {
+    let allow: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+    Response::builder(status)
+        .header("Allow", allow.join(", "))
+        .empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::RequestParser;
+
+    fn request(method: &str, target: &str) -> Request {
+        let raw = format!("{} {} HTTP/1.1\r\nHost: test\r\n\r\n", method, target);
+        RequestParser::new()
+            .parse(raw.as_bytes())
+            .unwrap()
+            .unwrap()
+            .0
+    }
+
+    fn test_router() -> Router {
+        Router::new()
+            .get("/users/:id", |request: Request| async move {
+                let id = request.params.get("id").unwrap_or_default().to_string();
+                Response::builder(StatusCode::Ok).body(id)
+            })
+            .get("/users/me", |_| async {
+                Response::new(StatusCode::Accepted)
+            })
+            .delete("/users/:id", |_| async {
+                Response::new(StatusCode::NoContent)
+            })
+            .get("/files/*path", |request: Request| async move {
+                let path = request.params.get("path").unwrap_or_default().to_string();
+                Response::builder(StatusCode::Ok).body(path)
+            })
+    }
+
+    fn body_text(response: &Response) -> String {
+        match &response.body {
+            crate::response::Body::Bytes(bytes) => String::from_utf8_lossy(bytes).to_string(),
+            _ => String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn captures_params_and_prefers_literals() {
+        let router = test_router();
+        let response = router.handle(request("GET", "/users/42?x=1")).await;
+        assert_eq!(body_text(&response), "42");
+        let response = router.handle(request("GET", "/users/me")).await;
+        assert_eq!(response.status, StatusCode::Accepted);
+        let response = router.handle(request("GET", "/files/css/site.css")).await;
+        assert_eq!(body_text(&response), "css/site.css");
+        let response = router.handle(request("GET", "/users/j%C3%B6rg%2Fx")).await;
+        assert_eq!(body_text(&response), "jörg/x");
+        let response = router.handle(request("GET", "/nothing")).await;
+        assert_eq!(response.status, StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn answers_405_head_and_options() {
+        let router = test_router();
+        let response = router.handle(request("POST", "/users/42")).await;
+        assert_eq!(response.status, StatusCode::MethodNotAllowed);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD, DELETE, OPTIONS"));
+        let response = router.handle(request("HEAD", "/users/42")).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        let response = router.handle(request("OPTIONS", "/users/42")).await;
+        assert_eq!(response.status, StatusCode::NoContent);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD, DELETE, OPTIONS"));
+    }
+}
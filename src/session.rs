@@ -0,0 +1,567 @@
+#![forbid(unsafe_code)]
+
+use crate::cookie::{Cookie, SameSite};
+use crate::error::SessionError;
+use crate::request::Request;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A server-side session. Clients only ever see its id, in a cookie.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: String,
+    /// The logged in user, None for an anonymous session.
+    pub username: Option<String>,
+    /// Anything else handlers want to keep across requests.
+    pub data: HashMap<String, String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    // The username and data as they were loaded, so unchanged sessions aren't written back.
+    loaded_username: Option<String>,
+    loaded_data: HashMap<String, String>,
+}
+
+impl Session {
+    fn new(username: Option<&str>) -> Session {
+        let now = Utc::now();
+        Session {
+            id: new_session_id(),
+            username: username.map(str::to_string),
+            data: HashMap::new(),
+            created_at: now,
+            last_seen_at: now,
+            loaded_username: None,
+            loaded_data: HashMap::new(),
+        }
+    }
+
+    fn mark_saved(&mut self) {
+        self.loaded_username = self.username.clone();
+        self.loaded_data = self.data.clone();
+    }
+}
+
+// 256 bits from a CSPRNG, hex encoded.
+fn new_session_id() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+/// Where sessions are kept between requests.
+pub trait SessionStore: Send + Sync + 'static {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Session>, SessionError>>;
+    /// Inserts the session, or replaces the one with the same id.
+    fn save<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, Result<(), SessionError>>;
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), SessionError>>;
+    /// Sets when the session was last seen, leaving its data alone.
+    fn touch<'a>(
+        &'a self,
+        id: &'a str,
+        last_seen_at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), SessionError>>;
+    /// Removes sessions last seen before 'idle_since' or created before 'created_since'.
+    fn purge_expired(
+        &self,
+        idle_since: DateTime<Utc>,
+        created_since: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<(), SessionError>>;
+}
+
+/// Keeps sessions in memory. They are lost on restart and not shared between servers,
+/// which is fine for development and single instances.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn sessions(&self) -> std::sync::MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Session>, SessionError>> {
+        let session = self.sessions().get(id).cloned();
+        Box::pin(async move { Ok(session) })
+    }
+
+    fn save<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, Result<(), SessionError>> {
+        self.sessions().insert(session.id.clone(), session.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), SessionError>> {
+        self.sessions().remove(id);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn touch<'a>(
+        &'a self,
+        id: &'a str,
+        last_seen_at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), SessionError>> {
+        if let Some(session) = self.sessions().get_mut(id) {
+            session.last_seen_at = last_seen_at;
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn purge_expired(
+        &self,
+        idle_since: DateTime<Utc>,
+        created_since: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<(), SessionError>> {
+        self.sessions().retain(|_, session| {
+            session.last_seen_at >= idle_since && session.created_at >= created_since
+        });
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Keeps sessions in the 'sessions' table, see '.github/workflows/init.sql'.
+#[derive(Debug, Clone)]
+pub struct PgStore {
+    pool: PgPool,
+}
+
+impl PgStore {
+    pub fn new(pool: PgPool) -> PgStore {
+        PgStore { pool }
+    }
+}
+
+impl SessionStore for PgStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Session>, SessionError>> {
+        Box::pin(async move {
+            let row = sqlx::query!(
+                r#"
+                    SELECT id, username, data, created_at, last_seen_at
+                    FROM sessions
+                    WHERE id = $1
+                "#,
+                id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+            let Some(row) = row else {
+                return Ok(None);
+            };
+            let data = serde_json::from_value(row.data).map_err(|err| {
+                SessionError::Corrupt(format!("data of session '{}': {}", row.id, err))
+            })?;
+            Ok(Some(Session {
+                id: row.id,
+                username: row.username,
+                data,
+                created_at: row.created_at,
+                last_seen_at: row.last_seen_at,
+                loaded_username: None,
+                loaded_data: HashMap::new(),
+            }))
+        })
+    }
+
+    fn save<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, Result<(), SessionError>> {
+        Box::pin(async move {
+            let data = serde_json::to_value(&session.data).unwrap_or_default();
+            sqlx::query!(
+                r#"
+                    INSERT INTO sessions (id, username, data, created_at, last_seen_at)
+                    VALUES ( $1, $2, $3, $4, $5 )
+                    ON CONFLICT (id) DO UPDATE
+                    SET username = $2, data = $3, last_seen_at = $5
+                "#,
+                session.id,
+                session.username,
+                data,
+                session.created_at,
+                session.last_seen_at
+            )
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), SessionError>> {
+        Box::pin(async move {
+            sqlx::query!("DELETE FROM sessions WHERE id = $1", id)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn touch<'a>(
+        &'a self,
+        id: &'a str,
+        last_seen_at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), SessionError>> {
+        Box::pin(async move {
+            sqlx::query!(
+                "UPDATE sessions SET last_seen_at = $2 WHERE id = $1",
+                id,
+                last_seen_at
+            )
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn purge_expired(
+        &self,
+        idle_since: DateTime<Utc>,
+        created_since: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<(), SessionError>> {
+        Box::pin(async move {
+            sqlx::query!(
+                "DELETE FROM sessions WHERE last_seen_at < $1 OR created_at < $2",
+                idle_since,
+                created_since
+            )
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+/// How session cookies are named and sent, and when sessions expire.
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// With the '__Host-' prefix browsers only accept the cookie over HTTPS, for this
+    /// exact host and path '/', so subdomains and plain HTTP pages can't plant one.
+    pub cookie_name: String,
+    pub secure: bool,
+    pub same_site: SameSite,
+    /// A session unused for this long is gone.
+    pub idle_timeout: Duration,
+    /// A session is gone this long after it was created, however busy.
+    pub absolute_timeout: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        SessionConfig {
+            cookie_name: "__Host-session".to_string(),
+            secure: true,
+            same_site: SameSite::Lax,
+            idle_timeout: Duration::from_secs(30 * 60),
+            absolute_timeout: Duration::from_secs(12 * 60 * 60),
+        }
+    }
+}
+
+/// Creates, finds and ends sessions. The server registers one in its state, backed by
+/// PostgreSQL when there is a database and memory otherwise:
+///
+/// ```ignore
+/// async fn whoami(request: Request) -> Response {
+///     let sessions = request.state::<SessionManager>().unwrap();
+///     match sessions.load(&request).await {
+///         Ok(Some(Session { username: Some(user), .. })) => Response::html(StatusCode::Ok, user),
+///         _ => Response::new(StatusCode::Unauthorized),
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SessionManager {
+    store: Arc<dyn SessionStore>,
+    config: SessionConfig,
+}
+
+impl SessionManager {
+    pub fn new(store: impl SessionStore, config: SessionConfig) -> SessionManager {
+        SessionManager {
+            store: Arc::new(store),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    /// The session the request's cookie points to, if it exists and hasn't expired.
+    /// Loading counts as activity and pushes the idle expiry back.
+    pub async fn load(&self, request: &Request) -> Result<Option<Session>, SessionError> {
+        let Some(id) = self.session_id(request) else {
+            return Ok(None);
+        };
+        let Some(mut session) = self.store.load(&id).await? else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        if self.is_expired(&session, now) {
+            self.store.delete(&session.id).await?;
+            return Ok(None);
+        }
+        // Only the expiry moves, so concurrent requests can't undo each other's changes.
+        session.last_seen_at = now;
+        self.store.touch(&session.id, now).await?;
+        session.mark_saved();
+        Ok(Some(session))
+    }
+
+    /// Stores changes a handler made to a session's username or data. Does nothing if
+    /// there are none.
+    pub async fn save(&self, session: &mut Session) -> Result<(), SessionError> {
+        if session.username == session.loaded_username && session.data == session.loaded_data {
+            return Ok(());
+        }
+        self.store.save(session).await?;
+        session.mark_saved();
+        Ok(())
+    }
+
+    /// Starts a session for 'username' after a successful login. Any session the client
+    /// had is dropped, so an id planted before the login is worthless (session fixation).
+    /// Returns the session and the cookie to send with the response.
+    pub async fn login(
+        &self,
+        request: &Request,
+        username: &str,
+    ) -> Result<(Session, Cookie), SessionError> {
+        if let Some(old_id) = self.session_id(request) {
+            self.store.delete(&old_id).await?;
+        }
+        let now = Utc::now();
+        self.store
+            .purge_expired(
+                now - chrono_duration(self.config.idle_timeout),
+                now - chrono_duration(self.config.absolute_timeout),
+            )
+            .await?;
+        let mut session = Session::new(Some(username));
+        self.store.save(&session).await?;
+        session.mark_saved();
+        // It lives as long as the session can, the idle expiry is up to the server.
+        let cookie = Cookie::new(&self.config.cookie_name, &session.id)?
+            .max_age(self.config.absolute_timeout);
+        Ok((session, self.cookie_attributes(cookie)))
+    }
+
+    /// Ends the request's session. Returns the cookie that removes the session cookie.
+    pub async fn logout(&self, request: &Request) -> Result<Cookie, SessionError> {
+        if let Some(id) = self.session_id(request) {
+            self.store.delete(&id).await?;
+        }
+        let cookie = Cookie::removal(&self.config.cookie_name)?;
+        Ok(self.cookie_attributes(cookie))
+    }
+
+    fn session_id(&self, request: &Request) -> Option<String> {
+        request
+            .cookies()
+            .get(&self.config.cookie_name)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    }
+
+    fn cookie_attributes(&self, cookie: Cookie) -> Cookie {
+        cookie
+            .path("/")
+            .secure(self.config.secure)
+            .http_only(true)
+            .same_site(self.config.same_site)
+    }
+
+    fn is_expired(&self, session: &Session, now: DateTime<Utc>) -> bool {
+        now - session.last_seen_at > chrono_duration(self.config.idle_timeout)
+            || now - session.created_at > chrono_duration(self.config.absolute_timeout)
+    }
+}
+
+fn chrono_duration(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::max_value())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::RequestParser;
+
+    fn request(cookie: Option<&str>) -> Request {
+        let cookie = cookie
+            .map(|value| format!("Cookie: theme=dark; {}\r\n", value))
+            .unwrap_or_default();
+        let raw = format!("GET / HTTP/1.1\r\nHost: test\r\n{}\r\n", cookie);
+        RequestParser::new()
+            .parse(raw.as_bytes())
+            .unwrap()
+            .unwrap()
+            .0
+    }
+
+    fn cookie_pair(cookie: &Cookie) -> String {
+        format!("{}={}", cookie.name(), cookie.value())
+    }
+
+    #[tokio::test]
+    async fn logs_in_rotates_and_logs_out() {
+        let sessions = SessionManager::new(MemoryStore::new(), SessionConfig::default());
+        let (first, set_cookie) = sessions.login(&request(None), "mock1").await.unwrap();
+        assert_eq!(first.id.len(), 64);
+        assert_eq!(
+            set_cookie.to_string(),
+            format!(
+                "__Host-session={}; Max-Age=43200; Path=/; Secure; HttpOnly; SameSite=Lax",
+                first.id
+            )
+        );
+
+        let cookie = &cookie_pair(&set_cookie);
+        let loaded = sessions
+            .load(&request(Some(cookie)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.username.as_deref(), Some("mock1"));
+
+        // Logging in again replaces the session instead of reusing the id.
+        let (second, _) = sessions
+            .login(&request(Some(cookie)), "mock1")
+            .await
+            .unwrap();
+        assert_ne!(second.id, first.id);
+        assert!(sessions
+            .load(&request(Some(cookie)))
+            .await
+            .unwrap()
+            .is_none());
+
+        let cookie = format!("__Host-session={}", second.id);
+        let cleared = sessions.logout(&request(Some(&cookie))).await.unwrap();
+        assert!(cleared
+            .to_string()
+            .starts_with("__Host-session=; Max-Age=0; Expires=Thu, 01 Jan 1970"));
+        assert!(sessions
+            .load(&request(Some(&cookie)))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(sessions.load(&request(None)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn expires_idle_and_old_sessions() {
+        let store = MemoryStore::new();
+        let sessions = SessionManager::new(store.clone(), SessionConfig::default());
+        let now = Utc::now();
+
+        let mut idle = Session::new(Some("idle"));
+        idle.last_seen_at = now - chrono::Duration::minutes(31);
+        let mut old = Session::new(Some("old"));
+        old.created_at = now - chrono::Duration::hours(13);
+        let active = Session::new(Some("active"));
+        for session in [&idle, &old, &active] {
+            store.save(session).await.unwrap();
+        }
+
+        for (session, alive) in [(&idle, false), (&old, false), (&active, true)] {
+            let cookie = format!("__Host-session={}", session.id);
+            let loaded = sessions.load(&request(Some(&cookie))).await.unwrap();
+            assert_eq!(loaded.is_some(), alive, "{:?}", session.username);
+        }
+        assert_eq!(store.sessions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn saves_only_changed_sessions() {
+        let store = MemoryStore::new();
+        let sessions = SessionManager::new(store.clone(), SessionConfig::default());
+        let mut session = Session::new(Some("mock1"));
+        session.last_seen_at = Utc::now() - chrono::Duration::minutes(10);
+        store.save(&session).await.unwrap();
+        let cookie = format!("__Host-session={}", session.id);
+
+        // Two requests with the same session at once, only the first changes it.
+        let mut first = sessions
+            .load(&request(Some(&cookie)))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut second = sessions
+            .load(&request(Some(&cookie)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(first.last_seen_at > session.last_seen_at);
+        first.data.insert("theme".to_string(), "dark".to_string());
+        sessions.save(&mut first).await.unwrap();
+        sessions.save(&mut second).await.unwrap();
+        sessions
+            .load(&request(Some(&cookie)))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let stored = store.sessions()[&session.id].clone();
+        assert_eq!(stored.data["theme"], "dark");
+        assert!(stored.last_seen_at > session.last_seen_at);
+
+        // Downgrading to an anonymous session is a change too.
+        let mut loaded = sessions
+            .load(&request(Some(&cookie)))
+            .await
+            .unwrap()
+            .unwrap();
+        loaded.username = None;
+        sessions.save(&mut loaded).await.unwrap();
+        let reloaded = sessions
+            .load(&request(Some(&cookie)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reloaded.username, None);
+        assert_eq!(reloaded.data["theme"], "dark");
+    }
+
+    #[tokio::test]
+    async fn stores_sessions_in_postgres() {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")
+            .expect("Failed to read test 'database_url' env variable.");
+        let store = PgStore::new(PgPool::connect(&database_url).await.unwrap());
+
+        let mut session = Session::new(Some("mock1"));
+        session.data.insert("theme".to_string(), "dark".to_string());
+        // Postgres keeps microseconds.
+        session.created_at = DateTime::from_timestamp(session.created_at.timestamp(), 0).unwrap();
+        session.last_seen_at = session.created_at;
+        store.save(&session).await.unwrap();
+        assert_eq!(
+            store.load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+
+        let later = session.last_seen_at + chrono::Duration::minutes(5);
+        store.touch(&session.id, later).await.unwrap();
+        let touched = store.load(&session.id).await.unwrap().unwrap();
+        assert_eq!(touched.last_seen_at, later);
+        assert_eq!(touched.data, session.data);
+
+        // A row that doesn't hold a map of strings is an error, not an empty session.
+        sqlx::query("UPDATE sessions SET data = '[1, 2]' WHERE id = $1")
+            .bind(&session.id)
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        assert!(matches!(
+            store.load(&session.id).await,
+            Err(SessionError::Corrupt(_))
+        ));
+
+        store.delete(&session.id).await.unwrap();
+        assert_eq!(store.load(&session.id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,113 @@
+#![forbid(unsafe_code)]
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Shared application state: at most one value per type, registered on the `Server`
+/// before it starts and handed to every request, so handlers and middleware can reach
+/// the database pool, configuration or their own services without globals.
+///
+/// ```ignore
+/// struct Visits(AtomicU64);
+///
+/// async fn count(request: Request) -> Response {
+///     let visits = request.state::<Visits>().unwrap();
+///     let n = visits.0.fetch_add(1, Ordering::Relaxed) + 1;
+///     Response::html(StatusCode::Ok, format!("<p>Visit number {}</p>", n))
+/// }
+///
+/// let server = Server::init(opts)?
+///     .with_state(Visits(AtomicU64::new(0)))
+///     .with_handler(Router::new().get("/", count));
+/// ```
+///
+/// Cloning is cheap, clones share the values. The values themselves are immutable,
+/// use atomics or a lock inside them for anything that changes.
+#[derive(Clone, Default)]
+pub struct AppState {
+    values: Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl AppState {
+    pub fn new() -> AppState {
+        AppState::default()
+    }
+
+    /// Adds 'value', replacing an earlier value of the same type. Clones made before
+    /// don't see it.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        Arc::make_mut(&mut self.values).insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Like `get`, but the value can outlive the request, e.g. to move it into a task.
+    pub fn get_arc<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .cloned()
+            .and_then(|value| value.downcast().ok())
+    }
+
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl fmt::Debug for AppState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AppState({} values)", self.values.len())
+    }
+}
+
+// Two states are equal when they are clones of each other.
+impl PartialEq for AppState {
+    fn eq(&self, other: &AppState) -> bool {
+        Arc::ptr_eq(&self.values, &other.values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Debug, PartialEq)]
+    struct Config(&'static str);
+
+    #[test]
+    fn stores_one_value_per_type() {
+        let mut state = AppState::new();
+        state.insert(Config("first"));
+        state.insert(AtomicU32::new(1));
+        let before = state.clone();
+        state.insert(Config("second"));
+
+        assert_eq!(state.get::<Config>(), Some(&Config("second")));
+        assert_eq!(before.get::<Config>(), Some(&Config("first")));
+        assert_eq!(state.len(), 2);
+        assert!(state.get::<String>().is_none());
+
+        let counter = state.get_arc::<AtomicU32>().unwrap();
+        counter.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(state.get::<AtomicU32>().unwrap().load(Ordering::Relaxed), 2);
+        assert_eq!(
+            before.get::<AtomicU32>().unwrap().load(Ordering::Relaxed),
+            2
+        );
+    }
+}
@@ -0,0 +1,625 @@
+#![forbid(unsafe_code)]
+
+use crate::asset_cache::{Asset, AssetCache};
+use crate::compression::{negotiate, Encoding};
+use crate::conditional::{http_date, is_not_modified, EntityTag};
+use crate::handler::Handler;
+use crate::headers::Headers;
+use crate::query::decode_path_segment;
+use crate::range::{byteranges_body, range_request, RangeRequest, RangeSource};
+use crate::request::Request;
+use crate::response::{Body, Response};
+use crate::status::StatusCode;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncSeekExt, Result as IoResult};
+
+/// Serves the files under a root directory. Mount it on a wildcard route named 'path' to
+/// choose the prefix, or on a plain route to map the whole request path onto the root:
+///
+/// ```ignore
+/// let router = Router::new()
+///     .get("/static/*path", StaticFiles::new("resources/html"))
+///     .get("/favicon.ico", StaticFiles::new("resources/html"));
+/// ```
+///
+/// Paths are canonicalized and have to stay under the root, so neither '../' nor symlinks
+/// pointing outside of it can be used to read other files. Hidden files (starting with a
+/// dot) are never served, and directories only through their index file.
+///
+/// Every file gets a strong `ETag` and `Last-Modified`, so clients can revalidate with
+/// `If-None-Match`/`If-Modified-Since` and get a 304. The tag is a hash of the file's
+/// path, size, modification time and inode (see `EntityTag::from_metadata`), not of its
+/// content: it changes whenever the file is written or replaced, even with the same
+/// bytes, and copies on other servers get different tags.
+/// GET requests can ask for byte ranges, answered with 206 or 416.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index_file: Option<String>,
+    cache_rules: Vec<(String, String)>,
+    default_cache_control: Option<String>,
+    precompressed: bool,
+    cache: Option<AssetCache>,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index_file: Some("index.html".to_string()),
+            cache_rules: Vec::new(),
+            default_cache_control: Some("no-cache".to_string()),
+            precompressed: false,
+            cache: None,
+        }
+    }
+
+    /// Serves 'file.br' or 'file.gz' instead of 'file' when it exists and the client
+    /// accepts that coding, so assets can be compressed once at build time.
+    pub fn precompressed(mut self, precompressed: bool) -> StaticFiles {
+        self.precompressed = precompressed;
+        self
+    }
+
+    /// Keeps small files in 'cache' instead of reading them on every request. They are
+    /// still checked against the disk each time, so edits show up right away.
+    pub fn cache(mut self, cache: AssetCache) -> StaticFiles {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Sends `Cache-Control: value` for files whose path under the root matches 'pattern',
+    /// where '*' matches anything, e.g. '*.css' or 'fonts/*'. The first matching rule wins.
+    pub fn cache_control(mut self, pattern: &str, value: &str) -> StaticFiles {
+        self.cache_rules
+            .push((pattern.to_string(), value.to_string()));
+        self
+    }
+
+    /// `Cache-Control` for files no rule matches. Defaults to 'no-cache', which lets clients
+    /// keep a copy but makes them revalidate it every time.
+    pub fn default_cache_control(mut self, value: Option<&str>) -> StaticFiles {
+        self.default_cache_control = value.map(str::to_string);
+        self
+    }
+
+    fn cache_control_for(&self, relative: &str) -> Option<&str> {
+        self.cache_rules
+            .iter()
+            .find(|(pattern, _)| glob_matches(pattern, relative))
+            .map(|(_, value)| value.as_str())
+            .or(self.default_cache_control.as_deref())
+    }
+
+    /// The file served for directory requests, None to answer those with 404.
+    pub fn index_file(mut self, index_file: Option<&str>) -> StaticFiles {
+        self.index_file = index_file.map(str::to_string);
+        self
+    }
+
+    async fn serve(&self, request: Request) -> Response {
+        let relative = match request.params.get("path") {
+            Some(path) => Some(path.to_string()),
+            None => request
+                .path()
+                .split('/')
+                .map(decode_path_segment)
+                .collect::<Option<Vec<String>>>()
+                .map(|segments| segments.join("/")),
+        };
+        let Ok(root) = fs::canonicalize(&self.root).await else {
+            eprintln!("Static file root {} is not available", self.root.display());
+            return Response::new(StatusCode::NotFound);
+        };
+        let path = match relative {
+            Some(relative) => self.resolve(&root, &relative).await,
+            None => None,
+        };
+        let Some(mut path) = path else {
+            return Response::new(StatusCode::NotFound);
+        };
+
+        if fs::metadata(&path).await.is_ok_and(|meta| meta.is_dir()) {
+            // Relative links in an index page only work from behind a trailing slash.
+            if !request.path().ends_with('/') {
+                let mut location = format!("/{}/", request.path().trim_matches('/'));
+                if let Some(query) = request.query() {
+                    location = format!("{}?{}", location, query);
+                }
+                return Response::builder(StatusCode::MovedPermanently)
+                    .header("Location", location)
+                    .empty();
+            }
+            match &self.index_file {
+                Some(index_file) => path.push(index_file),
+                None => return Response::new(StatusCode::NotFound),
+            }
+        }
+
+        match self.file_response(&request, &root, &path).await {
+            Ok(Some(response)) => response,
+            Ok(None) => Response::new(StatusCode::NotFound),
+            Err(e) => {
+                eprintln!("Error reading file {}: {}", path.display(), e);
+                Response::new(StatusCode::InternalServerError)
+            }
+        }
+    }
+
+    async fn file_response(
+        &self,
+        request: &Request,
+        root: &Path,
+        path: &Path,
+    ) -> IoResult<Option<Response>> {
+        // The index file (or a precompressed sibling) may be a symlink too.
+        let Some(path) = canonical_under(root, path).await else {
+            return Ok(None);
+        };
+        let path = path.as_path();
+        let mut variants = Vec::new();
+        if self.precompressed {
+            for encoding in [Encoding::Brotli, Encoding::Gzip] {
+                let mut sibling = path.as_os_str().to_owned();
+                sibling.push(format!(".{}", encoding.file_extension()));
+                if let Some(sibling) = canonical_under(root, Path::new(&sibling)).await {
+                    variants.push((encoding, sibling));
+                }
+            }
+        }
+        let available: Vec<Encoding> = variants.iter().map(|(encoding, _)| *encoding).collect();
+        let encoding = negotiate(request.header("Accept-Encoding"), &available);
+        let served = variants
+            .iter()
+            .find(|(variant, _)| Some(*variant) == encoding)
+            .map_or(path, |(_, sibling)| sibling.as_path());
+
+        let (mut content, etag, last_modified, len) = match self.cached(served).await? {
+            Some(asset) => {
+                let len = asset.content.len() as u64;
+                (
+                    Content::Cached(asset.content),
+                    asset.etag,
+                    asset.last_modified,
+                    len,
+                )
+            }
+            None => {
+                let file = match File::open(served).await {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e),
+                };
+                let metadata = file.metadata().await?;
+                if !metadata.is_file() {
+                    return Ok(None);
+                }
+                let etag = EntityTag::from_metadata(served, &metadata);
+                let last_modified = metadata.modified().ok();
+                (Content::File(file), etag, last_modified, metadata.len())
+            }
+        };
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        let mut headers = Headers::new();
+        headers.insert("ETag", etag.to_string());
+        if let Some(last_modified) = last_modified {
+            headers.insert("Last-Modified", http_date(last_modified));
+        }
+        if let Some(cache_control) = self.cache_control_for(&relative) {
+            headers.insert("Cache-Control", cache_control);
+        }
+
+        headers.insert("Accept-Ranges", "bytes");
+        if !variants.is_empty() {
+            headers.insert("Vary", "Accept-Encoding");
+        }
+        if let Some(encoding) = encoding {
+            headers.insert("Content-Encoding", encoding.as_str());
+        }
+
+        let content_type = mime_type(path);
+        let mut response = if is_not_modified(request, Some(&etag), last_modified) {
+            Response::new(StatusCode::NotModified)
+        } else {
+            match range_request(request, len, Some(&etag), last_modified) {
+                RangeRequest::Full => Response::builder(StatusCode::Ok)
+                    .content_type(content_type)
+                    .body(content.into_body(len)),
+                RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+                    let range = ranges[0];
+                    let body = match content {
+                        Content::Cached(bytes) => {
+                            Body::Bytes(bytes.slice(range.start as usize..=range.end as usize))
+                        }
+                        Content::File(ref mut file) => {
+                            file.seek(SeekFrom::Start(range.start)).await?;
+                            content.into_body(range.len())
+                        }
+                    };
+                    Response::builder(StatusCode::PartialContent)
+                        .content_type(content_type)
+                        .header("Content-Range", range.content_range(len))
+                        .body(body)
+                }
+                RangeRequest::Partial(ranges) => {
+                    let source = match content {
+                        Content::Cached(bytes) => RangeSource::Bytes(bytes),
+                        Content::File(file) => RangeSource::File(file),
+                    };
+                    let (body, boundary) = byteranges_body(source, &ranges, content_type, len);
+                    Response::builder(StatusCode::PartialContent)
+                        .content_type(&format!("multipart/byteranges; boundary={}", boundary))
+                        .body(body)
+                }
+                RangeRequest::Unsatisfiable => Response::builder(StatusCode::RangeNotSatisfiable)
+                    .header("Content-Range", format!("bytes */{}", len))
+                    .empty(),
+            }
+        };
+        for (name, value) in headers.iter() {
+            response.headers.insert(name, value);
+        }
+        Ok(Some(response))
+    }
+
+    async fn cached(&self, path: &Path) -> IoResult<Option<Asset>> {
+        let Some(cache) = &self.cache else {
+            return Ok(None);
+        };
+        match cache.get(path).await {
+            Ok(asset) => Ok(asset),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Maps a decoded request path onto the file system. None if it doesn't exist or would
+    // end up outside of the root.
+    async fn resolve(&self, root: &Path, relative: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for segment in relative.split('/').filter(|segment| !segment.is_empty()) {
+            // Covers '.', '..' and hidden files. Backslashes are separators on Windows.
+            if segment.starts_with('.') || segment.contains(['\\', '\0']) {
+                return None;
+            }
+            path.push(segment);
+        }
+        canonical_under(root, &path).await
+    }
+}
+
+// A file's content, either held by an asset cache or still on disk.
+enum Content {
+    Cached(Bytes),
+    File(File),
+}
+
+impl Content {
+    fn into_body(self, len: u64) -> Body {
+        match self {
+            Content::Cached(bytes) => Body::Bytes(bytes),
+            Content::File(file) => Body::File { file, len },
+        }
+    }
+}
+
+async fn canonical_under(root: &Path, path: &Path) -> Option<PathBuf> {
+    let canonical = fs::canonicalize(path).await.ok()?;
+    canonical.starts_with(root).then_some(canonical)
+}
+
+impl Handler for StaticFiles {
+    fn call(&self, request: Request) -> BoxFuture<'_, Response> {
+        Box::pin(self.serve(request))
+    }
+}
+
+// '*' matches any run of characters, '/' included.
+pub(crate) fn glob_matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Guesses the Content-Type from the file extension, falling back to a plain byte stream.
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=UTF-8",
+        "css" => "text/css; charset=UTF-8",
+        "js" | "mjs" => "text/javascript; charset=UTF-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=UTF-8",
+        "md" => "text/markdown; charset=UTF-8",
+        "csv" => "text/csv; charset=UTF-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::RequestParser;
+    use crate::router::Router;
+    use crate::test_support::TempDir;
+
+    fn request(target: &str) -> Request {
+        let raw = format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", target);
+        RequestParser::new()
+            .parse(raw.as_bytes())
+            .unwrap()
+            .unwrap()
+            .0
+    }
+
+    // root/{site.css, docs/index.html, .env, escape -> ../secret.txt}, secret.txt next to root.
+    // The symlink is only made on Unix, elsewhere '/static/escape' is simply missing.
+    fn fixture() -> TempDir {
+        let dir = TempDir::new("static");
+        let root = dir.join("root");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("site.css"), "body {}").unwrap();
+        std::fs::write(root.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+        std::fs::write(root.join(".env"), "SECRET=1").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("secret.txt"), root.join("escape")).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn serves_files_and_index_pages() {
+        let dir = fixture();
+        let router = Router::new().get("/static/*path", StaticFiles::new(dir.join("root")));
+
+        let response = router.handle(request("/static/site.css")).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/css; charset=UTF-8")
+        );
+        assert!(matches!(response.body, Body::File { len: 7, .. }));
+
+        let response = router.handle(request("/static/docs?x=1")).await;
+        assert_eq!(response.status, StatusCode::MovedPermanently);
+        assert_eq!(response.header("Location"), Some("/static/docs/?x=1"));
+        let response = router.handle(request("/static/docs/")).await;
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/html; charset=UTF-8")
+        );
+
+        let router = Router::new().get(
+            "/static/*path",
+            StaticFiles::new(dir.join("root")).index_file(None),
+        );
+        let response = router.handle(request("/static/docs/")).await;
+        assert_eq!(response.status, StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn blocks_traversal_hidden_files_and_symlink_escapes() {
+        let dir = fixture();
+        let router = Router::new().get("/static/*path", StaticFiles::new(dir.join("root")));
+        for target in [
+            "/static/../secret.txt",
+            "/static/%2e%2e/secret.txt",
+            "/static/docs/..%2F..%2Fsecret.txt",
+            "/static/..%5Csecret.txt",
+            "/static/.env",
+            "/static/escape",
+            "/static/missing.css",
+            "/static/%ff",
+        ] {
+            let response = router.handle(request(target)).await;
+            assert_eq!(response.status, StatusCode::NotFound, "{}", target);
+        }
+    }
+
+    #[tokio::test]
+    async fn revalidates_with_etag_and_last_modified() {
+        let dir = fixture();
+        let router = Router::new().get(
+            "/static/*path",
+            StaticFiles::new(dir.join("root")).cache_control("*.css", "public, max-age=60"),
+        );
+
+        let response = router.handle(request("/static/site.css")).await;
+        let etag = response.header("ETag").unwrap().to_string();
+        let last_modified = response.header("Last-Modified").unwrap().to_string();
+        assert_eq!(etag.len(), 34);
+        assert_eq!(response.header("Cache-Control"), Some("public, max-age=60"));
+        let response = router.handle(request("/static/docs/")).await;
+        assert_eq!(response.header("Cache-Control"), Some("no-cache"));
+
+        for header in [
+            format!("If-None-Match: {}", etag),
+            format!("If-Modified-Since: {}", last_modified),
+        ] {
+            let raw = format!(
+                "GET /static/site.css HTTP/1.1\r\nHost: test\r\n{}\r\n\r\n",
+                header
+            );
+            let (conditional, _) = RequestParser::new().parse(raw.as_bytes()).unwrap().unwrap();
+            let response = router.handle(conditional).await;
+            assert_eq!(response.status, StatusCode::NotModified, "{}", header);
+            assert_eq!(response.header("ETag"), Some(etag.as_str()));
+            assert!(matches!(response.body, Body::Empty));
+        }
+    }
+
+    #[test]
+    fn matches_cache_patterns() {
+        assert!(glob_matches("*.css", "css/site.css"));
+        assert!(glob_matches("fonts/*", "fonts/a.woff2"));
+        assert!(glob_matches("a*b*c", "aXbYc"));
+        assert!(!glob_matches("*.css", "site.css.map"));
+        assert!(!glob_matches("a*ab", "ab"));
+        assert!(glob_matches("home.html", "home.html"));
+    }
+
+    #[tokio::test]
+    async fn serves_byte_ranges() {
+        use futures::StreamExt;
+        use tokio::io::AsyncReadExt;
+
+        let dir = fixture();
+        let router = Router::new().get("/static/*path", StaticFiles::new(dir.join("root")));
+        let ranged = |range: &str| {
+            let raw = format!(
+                "GET /static/site.css HTTP/1.1\r\nHost: test\r\nRange: {}\r\n\r\n",
+                range
+            );
+            RequestParser::new()
+                .parse(raw.as_bytes())
+                .unwrap()
+                .unwrap()
+                .0
+        };
+
+        let response = router.handle(ranged("bytes=-2")).await;
+        assert_eq!(response.status, StatusCode::PartialContent);
+        assert_eq!(response.header("Content-Range"), Some("bytes 5-6/7"));
+        let Body::File { mut file, len: 2 } = response.body else {
+            panic!("expected a two byte file body");
+        };
+        let mut content = String::new();
+        file.read_to_string(&mut content).await.unwrap();
+        assert_eq!(content, "{}");
+
+        let response = router.handle(ranged("bytes=0-0, 5-")).await;
+        let content_type = response.header("Content-Type").unwrap().to_string();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let Body::Stream(chunks) = response.body else {
+            panic!("expected a streamed body");
+        };
+        let body: Vec<u8> = chunks.map(|chunk| chunk.to_vec()).concat().await;
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            format!(
+                "\r\n--{b}\r\nContent-Type: text/css; charset=UTF-8\r\n\
+                Content-Range: bytes 0-0/7\r\n\r\nb\
+                \r\n--{b}\r\nContent-Type: text/css; charset=UTF-8\r\n\
+                Content-Range: bytes 5-6/7\r\n\r\n{{}}\r\n--{b}--\r\n",
+                b = boundary
+            )
+        );
+
+        let response = router.handle(ranged("bytes=7-")).await;
+        assert_eq!(response.status, StatusCode::RangeNotSatisfiable);
+        assert_eq!(response.header("Content-Range"), Some("bytes */7"));
+    }
+
+    #[tokio::test]
+    async fn serves_cached_files_from_memory() {
+        let dir = fixture();
+        let files = StaticFiles::new(dir.join("root"));
+        let router = Router::new()
+            .get("/static/*path", files.clone())
+            .get("/cached/*path", files.cache(AssetCache::new()));
+
+        let uncached = router.handle(request("/static/site.css")).await;
+        let response = router.handle(request("/cached/site.css")).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.header("ETag"), uncached.header("ETag"));
+        assert!(matches!(&response.body, Body::Bytes(bytes) if bytes == "body {}"));
+
+        let raw = "GET /cached/site.css HTTP/1.1\r\nHost: test\r\nRange: bytes=1-3\r\n\r\n";
+        let ranged = RequestParser::new()
+            .parse(raw.as_bytes())
+            .unwrap()
+            .unwrap()
+            .0;
+        let response = router.handle(ranged).await;
+        assert_eq!(response.status, StatusCode::PartialContent);
+        assert_eq!(response.header("Content-Range"), Some("bytes 1-3/7"));
+        assert!(matches!(&response.body, Body::Bytes(bytes) if bytes == "ody"));
+
+        std::fs::write(dir.join("root/site.css"), "p {}").unwrap();
+        let response = router.handle(request("/cached/site.css")).await;
+        assert!(matches!(&response.body, Body::Bytes(bytes) if bytes == "p {}"));
+    }
+
+    #[tokio::test]
+    async fn serves_precompressed_siblings() {
+        let dir = fixture();
+        std::fs::write(dir.join("root/site.css.gz"), "gzipped").unwrap();
+        let router = Router::new().get(
+            "/static/*path",
+            StaticFiles::new(dir.join("root")).precompressed(true),
+        );
+        let with_encoding = |accept: &str| {
+            let raw = format!(
+                "GET /static/site.css HTTP/1.1\r\nHost: test\r\nAccept-Encoding: {}\r\n\r\n",
+                accept
+            );
+            RequestParser::new()
+                .parse(raw.as_bytes())
+                .unwrap()
+                .unwrap()
+                .0
+        };
+
+        let response = router.handle(with_encoding("gzip, br")).await;
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/css; charset=UTF-8")
+        );
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert!(matches!(response.body, Body::File { len: 7, .. }));
+
+        let response = router.handle(with_encoding("br")).await;
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    }
+}
@@ -1,40 +1,120 @@
-static _STATUS_100: &str = "HTTP/1.1 100 CONTINUE";
-static _STATUS_101: &str = "HTTP/1.1 101 SWITCHING PROTOCOLS";
-pub static STATUS_200: &str = "HTTP/1.1 200 OK";
-static _STATUS_201: &str = "HTTP/1.1 201 CREATED";
-static _STATUS_202: &str = "HTTP/1.1 202 ACCEPTED";
-static _STATUS_203: &str = "HTTP/1.1 203 NON-AUTHORITATIVE INFORMATION";
-static _STATUS_204: &str = "HTTP/1.1 204 NO CONTENT";
-static _STATUS_205: &str = "HTTP/1.1 205 RESET CONTENT";
-static _STATUS_206: &str = "HTTP/1.1 206 PARTIAL CONTENT";
-static _STATUS_300: &str = "HTTP/1.1 300 MULTIPLE CHOICES";
-static _STATUS_301: &str = "HTTP/1.1 301 MOVED PERMANENTLY";
-static _STATUS_302: &str = "HTTP/1.1 302 FOUND";
-static _STATUS_303: &str = "HTTP/1.1 303 SEE OTHER";
-static _STATUS_304: &str = "HTTP/1.1 304 NOT MODIFIED";
-static _STATUS_305: &str = "HTTP/1.1 305 USE PROXY";
-static _STATUS_307: &str = "HTTP/1.1 307 TEMPORARY REDIRECT";
-static _STATUS_400: &str = "HTTP/1.1 400 BAD REQUEST";
-pub static STATUS_401: &str = "HTTP/1.1 401 UNAUTHORIZED";
-static _STATUS_402: &str = "HTTP/1.1 402 PAYMENT REQUIRED";
-static _STATUS_403: &str = "HTTP/1.1 403 FORBIDDEN";
-pub static STATUS_404: &str = "HTTP/1.1 404 NOT FOUND";
-static _STATUS_405: &str = "HTTP/1.1 405 METHOD NOT ALLOWED";
-static _STATUS_406: &str = "HTTP/1.1 406 NOT ACCEPTABLE";
-static _STATUS_407: &str = "HTTP/1.1 407 PROXY AUTHENTICATION REQUIRED";
-static _STATUS_408: &str = "HTTP/1.1 408 REQUEST TIME-OUT";
-static _STATUS_409: &str = "HTTP/1.1 409 CONFLICT";
-static _STATUS_410: &str = "HTTP/1.1 410 GONE";
-static _STATUS_411: &str = "HTTP/1.1 411 LENGTH REQUIRED";
-static _STATUS_412: &str = "HTTP/1.1 412 PRECONDITION FAILED";
-static _STATUS_413: &str = "HTTP/1.1 413 REQUEST ENTITY TOO LARGE";
-static _STATUS_414: &str = "HTTP/1.1 414 REQUEST-URI TOO LARGE";
-static _STATUS_415: &str = "HTTP/1.1 415 UNSUPPORTED MEDIA TYPE";
-static _STATUS_416: &str = "HTTP/1.1 416 REQUESTED RANGE NOT SATISFIABLE";
-static _STATUS_417: &str = "HTTP/1.1 417 EXPECTATION FAILED";
-pub static STATUS_500: &str = "HTTP/1.1 500 INTERNAL SERVER ERROR";
-static _STATUS_501: &str = "HTTP/1.1 501 NOT IMPLEMENTED";
-static _STATUS_502: &str = "HTTP/1.1 502 BAD GATEWAY";
-static _STATUS_503: &str = "HTTP/1.1 503 SERVICE UNAVAILABLE";
-static _STATUS_504: &str = "HTTP/1.1 504 GATEWAY TIME-OUT";
-static _STATUS_505: &str = "HTTP/1.1 505 HTTP VERSION NOT SUPPORTED";
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+/// HTTP response status codes with their standard reason phrases (RFC 9110, 15).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatusCode {
+    Continue = 100,
+    SwitchingProtocols = 101,
+    Ok = 200,
+    Created = 201,
+    Accepted = 202,
+    NonAuthoritativeInformation = 203,
+    NoContent = 204,
+    ResetContent = 205,
+    PartialContent = 206,
+    MultipleChoices = 300,
+    MovedPermanently = 301,
+    Found = 302,
+    SeeOther = 303,
+    NotModified = 304,
+    TemporaryRedirect = 307,
+    PermanentRedirect = 308,
+    BadRequest = 400,
+    Unauthorized = 401,
+    PaymentRequired = 402,
+    Forbidden = 403,
+    NotFound = 404,
+    MethodNotAllowed = 405,
+    NotAcceptable = 406,
+    ProxyAuthenticationRequired = 407,
+    RequestTimeout = 408,
+    Conflict = 409,
+    Gone = 410,
+    LengthRequired = 411,
+    PreconditionFailed = 412,
+    PayloadTooLarge = 413,
+    UriTooLong = 414,
+    UnsupportedMediaType = 415,
+    RangeNotSatisfiable = 416,
+    ExpectationFailed = 417,
+    UnprocessableContent = 422,
+    TooManyRequests = 429,
+    RequestHeaderFieldsTooLarge = 431,
+    InternalServerError = 500,
+    NotImplemented = 501,
+    BadGateway = 502,
+    ServiceUnavailable = 503,
+    GatewayTimeout = 504,
+    HttpVersionNotSupported = 505,
+}
+
+impl StatusCode {
+    pub fn as_u16(self) -> u16 {
+        self as u16
+    }
+
+    pub fn reason_phrase(self) -> &'static str {
+        match self {
+            StatusCode::Continue => "Continue",
+            StatusCode::SwitchingProtocols => "Switching Protocols",
+            StatusCode::Ok => "OK",
+            StatusCode::Created => "Created",
+            StatusCode::Accepted => "Accepted",
+            StatusCode::NonAuthoritativeInformation => "Non-Authoritative Information",
+            StatusCode::NoContent => "No Content",
+            StatusCode::ResetContent => "Reset Content",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::MultipleChoices => "Multiple Choices",
+            StatusCode::MovedPermanently => "Moved Permanently",
+            StatusCode::Found => "Found",
+            StatusCode::SeeOther => "See Other",
+            StatusCode::NotModified => "Not Modified",
+            StatusCode::TemporaryRedirect => "Temporary Redirect",
+            StatusCode::PermanentRedirect => "Permanent Redirect",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Unauthorized => "Unauthorized",
+            StatusCode::PaymentRequired => "Payment Required",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::NotAcceptable => "Not Acceptable",
+            StatusCode::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::Conflict => "Conflict",
+            StatusCode::Gone => "Gone",
+            StatusCode::LengthRequired => "Length Required",
+            StatusCode::PreconditionFailed => "Precondition Failed",
+            StatusCode::PayloadTooLarge => "Content Too Large",
+            StatusCode::UriTooLong => "URI Too Long",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::ExpectationFailed => "Expectation Failed",
+            StatusCode::UnprocessableContent => "Unprocessable Content",
+            StatusCode::TooManyRequests => "Too Many Requests",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::NotImplemented => "Not Implemented",
+            StatusCode::BadGateway => "Bad Gateway",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+            StatusCode::GatewayTimeout => "Gateway Timeout",
+            StatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+
+    /// 1xx, 204 and 304 responses never carry a body (RFC 9112, 6.3).
+    pub fn allows_body(self) -> bool {
+        !matches!(self.as_u16(), 100..=199 | 204 | 304)
+    }
+
+    pub fn is_success(self) -> bool {
+        (200..300).contains(&self.as_u16())
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.as_u16(), self.reason_phrase())
+    }
+}
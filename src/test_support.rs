@@ -0,0 +1,31 @@
+#![forbid(unsafe_code)]
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory under the system temp directory for test fixtures. It is removed
+/// with everything in it when dropped, also when a failed assert unwinds the test.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(prefix: &str) -> TempDir {
+        let path =
+            std::env::temp_dir().join(format!("ironclad-{}-{:x}", prefix, rand::random::<u64>()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}